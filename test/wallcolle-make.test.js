@@ -0,0 +1,944 @@
+// Tests of wallcolle-make, run with: node --test test/
+// Builds run the script in a scratch copy of test/fixtures, which is laid out like a pack root
+
+const test = require('node:test');
+const assert = require('node:assert');
+const fs = require('fs');
+const os = require('os');
+const path = require('path');
+const spawn = require('child_process').spawnSync;
+const wallcolle = require('../wallcolle-make.js');
+
+const SCRIPT = path.join(__dirname, '..', 'wallcolle-make.js');
+const FIXTURES = path.join(__dirname, 'fixtures');
+
+// Fixture images are a few pixels wide, far below the pack policy
+const TINY = ['--min-source', '1x1'];
+
+// The space in the pack root makes sure paths reach external commands as single arguments
+const copyFixtures = function (t) {
+    let packroot = fs.mkdtempSync(path.join(os.tmpdir(), 'wallcolle test-'));
+    fs.cpSync(FIXTURES, packroot, { recursive: true });
+    t.after(function () {
+        fs.rmSync(packroot, { recursive: true, force: true });
+    });
+    return packroot;
+};
+
+const runCli = function (packroot, args, env) {
+    return spawn(process.execPath, [SCRIPT].concat(args), { cwd: packroot, encoding: 'utf8', env: Object.assign({}, process.env, env) });
+};
+
+// Puts a shell script on PATH under this name, standing in for a tool that is only looked up there.
+// The script may append to "$LOG"; env is for runCli
+const fakeTool = function (t, name, lines) {
+    let bindir = fs.mkdtempSync(path.join(os.tmpdir(), 'wallcolle-bin-'));
+    t.after(function () {
+        fs.rmSync(bindir, { recursive: true, force: true });
+    });
+    let logpath = path.join(bindir, `${name}.log`);
+    fs.writeFileSync(path.join(bindir, name), ['#!/bin/sh', `LOG='${logpath}'`].concat(lines, ['']).join('\n'), { mode: 0o755 });
+    return {
+        env: { PATH: `${bindir}:${process.env.PATH}` },
+        // Lines the script appended to "$LOG"
+        logged: function () {
+            return fs.existsSync(logpath) ? fs.readFileSync(logpath, 'utf8').split('\n').filter(function (x) { return x !== '' }) : [];
+        }
+    };
+};
+
+// Stands in for ImageMagick: convert logs its arguments and copies its input to its output, unless told
+// to fail or to hang for some seconds, and -version prints the given banner; identify answers from the fixture
+// image's size and the given properties
+const fakeImageMagick = function (t, props) {
+    props = Object.assign({ version: 'Version: ImageMagick 6.9.11-60 Q16 x86_64', orientation: 'Undefined', profile: '', fail: false, hang: 0 }, props);
+    let bindir = fs.mkdtempSync(path.join(os.tmpdir(), 'wallcolle-bin-'));
+    t.after(function () {
+        fs.rmSync(bindir, { recursive: true, force: true });
+    });
+    fs.writeFileSync(path.join(bindir, 'convert'), [
+        '#!/bin/sh',
+        'case "$1" in',
+        `    -version) echo '${props.version}'; exit 0 ;;`,
+        '    -list) printf "      PNG* PNG       rw-   Portable Network Graphics\\n     JPEG* JPEG      rw-   Joint Photographic Experts Group\\n"; exit 0 ;;',
+        'esac',
+        `printf '%s\\n' "$@" "" >> "${bindir}/convert.log"`,
+        props.fail ? 'exit 1' : '',
+        props.hang ? `exec sleep ${props.hang}` : '',
+        'for arg; do out="$arg"; done',
+        'cp "$1" "${out#*:}"',
+        ''
+    ].join('\n'), { mode: 0o755 });
+    fs.writeFileSync(path.join(bindir, 'identify'), [
+        '#!/bin/sh',
+        'case "$2" in',
+        `    *orientation*) printf '%s\\n' '${props.orientation}' '${props.profile}' ;;`,
+        '    *) echo "8 4" ;;',
+        'esac',
+        ''
+    ].join('\n'), { mode: 0o755 });
+    return {
+        convert: path.join(bindir, 'convert'),
+        // Arguments of each convert run
+        runs: function () {
+            let logpath = path.join(bindir, 'convert.log');
+            if (!fs.existsSync(logpath)) {
+                return [];
+            };
+            return fs.readFileSync(logpath, 'utf8').split('\n\n').filter(function (x) { return x !== '' }).map(function (x) { return x.split('\n') });
+        }
+    };
+};
+
+// Serves files of a directory over HTTP from a child process, since runCli blocks this one; resolves to
+// the base URL
+const serveDirectory = function (t, dirpath) {
+    let server = require('child_process').spawn(process.execPath, ['-e', `
+        const server = require('http').createServer(function (req, res) {
+            require('fs').readFile(require('path').join(process.argv[1], req.url), function (err, data) {
+                res.statusCode = err ? 404 : 200;
+                res.end(err ? '' : data);
+            });
+        });
+        server.listen(0, '127.0.0.1', function () {
+            console.log(server.address().port);
+        });
+    `, dirpath], { stdio: ['ignore', 'pipe', 'inherit'] });
+    t.after(function () {
+        server.kill();
+    });
+    return new Promise(function (resolve) {
+        server.stdout.once('data', function (data) {
+            resolve(`http://127.0.0.1:${String(data).trim()}`);
+        });
+    });
+};
+
+// --------------------------------------
+// Helpers
+
+test('normalizeLicense squashes common spellings into SPDX identifiers', function () {
+    assert.strictEqual(wallcolle.normalizeLicense('CC BY-SA 4.0'), 'CC-BY-SA-4.0');
+    assert.strictEqual(wallcolle.normalizeLicense('cc_by_4'), 'CC-BY-4.0');
+    assert.strictEqual(wallcolle.normalizeLicense(' CC0 '), 'CC0-1.0');
+    assert.strictEqual(wallcolle.normalizeLicense('GPLv2+'), 'GPL-2.0-or-later');
+    assert.strictEqual(wallcolle.normalizeLicense('LAL 1.3'), 'FAL-1.3');
+    assert.strictEqual(wallcolle.normalizeLicense('LicenseRef-Studio'), 'LicenseRef-Studio');
+    assert.strictEqual(wallcolle.normalizeLicense('All rights reserved'), null);
+});
+
+test('parseDeclaration reads ARTIST:INDEX with stray whitespace and a trailing comma', function () {
+    assert.deepStrictEqual(wallcolle.parseDeclaration(' alice : 3 ,'), { uname: 'alice', index: '3', token: 'alice:3', comma: true });
+    assert.deepStrictEqual(wallcolle.parseDeclaration('bob:0'), { uname: 'bob', index: '0', token: 'bob:0', comma: false });
+    // Decomposed input matches the composed name
+    assert.strictEqual(wallcolle.parseDeclaration('zoë:1').uname, 'zoë');
+    assert.strictEqual(wallcolle.parseDeclaration('alice'), null);
+    assert.strictEqual(wallcolle.parseDeclaration('alice:one'), null);
+    assert.strictEqual(wallcolle.parseDeclaration('a;b:1'), null);
+});
+
+test('parseSize takes decimal and binary units', function () {
+    assert.strictEqual(wallcolle.parseSize('500M'), 500000000);
+    assert.strictEqual(wallcolle.parseSize('2GiB'), 2 * 1024 * 1024 * 1024);
+    assert.strictEqual(wallcolle.parseSize('1.5 KB'), 1500);
+    assert.strictEqual(wallcolle.parseSize('12'), 12);
+    assert.strictEqual(wallcolle.parseSize('lots'), null);
+});
+
+test('getFocusCrop centers the window on the focus point within the edges', function () {
+    let src = { width: 4000, height: 2000 };
+    assert.deepStrictEqual(wallcolle.getFocusCrop(src, '1000x1000', { x: 0.5, y: 0.5 }), { width: 1000, height: 1000, x: 500, y: 0 });
+    assert.deepStrictEqual(wallcolle.getFocusCrop(src, '1000x1000', { x: 0, y: 0.5 }), { width: 1000, height: 1000, x: 0, y: 0 });
+    assert.deepStrictEqual(wallcolle.getFocusCrop(src, '1000x1000', { x: 1, y: 0.5 }), { width: 1000, height: 1000, x: 1000, y: 0 });
+    assert.deepStrictEqual(wallcolle.getFocusCrop(src, '800x600', { x: 0.25, y: 0 }), { width: 800, height: 600, x: 0, y: 0 });
+});
+
+test('escapeDesktopValue trims, drops control characters and escapes line breaks', function () {
+    assert.strictEqual(wallcolle.escapeDesktopValue('  Sunset\\Hill\n2\x01 '), 'Sunset\\\\Hill\\n2');
+    assert.strictEqual(wallcolle.escapeDesktopValue('a=b;c'), 'a=b;c');
+    assert.strictEqual(wallcolle.escapeDesktopList(['sea;side', 'night']), 'sea\\;side;night;');
+});
+
+test('escapeXml escapes markup and quotes', function () {
+    assert.strictEqual(wallcolle.escapeXml(`<Tom & "Jerry's">`), '&lt;Tom &amp; &quot;Jerry&apos;s&quot;&gt;');
+});
+
+test('groupByArtist keeps artists in order of first appearance', function () {
+    let a1 = { uname: 'alice' };
+    let b1 = { uname: 'bob' };
+    let a2 = { uname: 'alice' };
+    assert.deepStrictEqual(wallcolle.groupByArtist([a1, b1, a2, a1]), [
+        { uname: 'alice', entries: [a1, a2] },
+        { uname: 'bob', entries: [b1] }
+    ]);
+    assert.deepStrictEqual(wallcolle.groupByArtist([]), []);
+});
+
+test('MemFs records operations and keeps written files in memory', function () {
+    let memfs = new wallcolle.MemFs();
+    memfs.mkdir('out/usr');
+    memfs.write('out/usr/a.txt', 'hello');
+    memfs.run(['convert', 'in.png', 'out/usr/b.png']);
+    assert.strictEqual(memfs.read('out/usr/a.txt').toString(), 'hello');
+    assert.strictEqual(memfs.read('out/usr/b.png').length, 0);
+    assert.deepStrictEqual(memfs.ops.map(function (op) { return op[0] }), ['mkdir', 'write', 'run']);
+    assert.deepStrictEqual(memfs.ops[2], ['run', 'convert', 'in.png', 'out/usr/b.png']);
+    assert.strictEqual(memfs.read('out//usr/a.txt').toString(), 'hello');
+    assert.strictEqual(memfs.treeSize('out'), 5);
+    memfs.remove('out/usr');
+    assert.strictEqual(memfs.treeSize('out'), 0);
+});
+
+// --------------------------------------
+// Builds
+
+test('a dry run lists what it would write and leaves the destination alone', function (t) {
+    let packroot = copyFixtures(t);
+    let result = runCli(packroot, TINY.concat(['--dry-run', 'packs/basic', 'out', 'NORMAL']));
+    assert.strictEqual(result.status, 0, result.stderr);
+    assert.match(result.stdout, /Dry run, recorded \d+ operations:/);
+    assert.match(result.stdout, /^    copy .+\/contributors\/alice\/1\.png out\/+usr\/share\/backgrounds\/basic--alice--Morning\/basic--alice--Morning\.png$/m);
+    assert.match(result.stdout, /^    write out\/+usr\/share\/wallpapers\/basic--bob--Lake\/metadata\.desktop$/m);
+    assert.ok(!fs.existsSync(path.join(packroot, 'out')));
+});
+
+test('a dry run leaves dist and db.json in the pack root alone', function (t) {
+    let packroot = copyFixtures(t);
+    fs.mkdirSync(path.join(packroot, 'dist'));
+    fs.writeFileSync(path.join(packroot, 'dist/keepme.txt'), 'kept');
+    let result = runCli(packroot, TINY.concat(['--dry-run', 'packs/basic', 'out', 'NORMAL']));
+    assert.strictEqual(result.status, 0, result.stderr);
+    assert.deepStrictEqual(fs.readdirSync(path.join(packroot, 'dist')), ['keepme.txt']);
+    assert.ok(!fs.existsSync(path.join(packroot, 'db.json')));
+    assert.match(result.stdout, /^    remove \.\/dist\/keepme\.txt$/m);
+    assert.match(result.stdout, /^    write \.\/db\.json$/m);
+    assert.match(result.stdout, /^    copy .+\/contributors\/bob\/1\.png \.\/dist\/Lake\.png$/m);
+});
+
+test('a build writes the package tree', function (t) {
+    let packroot = copyFixtures(t);
+    let result = runCli(packroot, TINY.concat(['packs/basic', 'out', 'NORMAL']));
+    assert.strictEqual(result.status, 0, result.stderr);
+    let backgrounds = path.join(packroot, 'out/usr/share/backgrounds');
+    assert.deepStrictEqual(fs.readFileSync(`${backgrounds}/basic--alice--Morning/basic--alice--Morning.png`), fs.readFileSync(path.join(FIXTURES, 'contributors/alice/1.png')));
+    let desktop = fs.readFileSync(path.join(packroot, 'out/usr/share/wallpapers/basic--alice--Sunset/metadata.desktop')).toString();
+    assert.match(desktop, /^Name=Sunset$/m);
+    assert.match(desktop, /^X-KDE-PluginInfo-License=CC0-1.0$/m);
+    let album = fs.readFileSync(path.join(packroot, 'out/usr/share/background-properties/Basic.xml')).toString();
+    assert.match(album, /<filename>\/usr\/share\/backgrounds\/basic--bob--Lake\/basic--bob--Lake\.png<\/filename>/);
+    // Bob's wallpaper is taller than wide
+    assert.match(album, /<options>centered<\/options>/);
+});
+
+test('--only-changed rebuilds entries whose metadata changed', function (t) {
+    let packroot = copyFixtures(t);
+    let lockpath = path.join(packroot, 'pack.lock');
+    let result = runCli(packroot, TINY.concat(['--write-lock', lockpath, 'packs/basic', 'out', 'NORMAL']));
+    assert.strictEqual(result.status, 0, result.stderr);
+    let metapath = path.join(packroot, 'contributors/alice/me.json');
+    let userdata = JSON.parse(fs.readFileSync(metapath));
+    userdata.license = 'CC-BY-SA-4.0';
+    fs.writeFileSync(metapath, JSON.stringify(userdata));
+    result = runCli(packroot, TINY.concat(['--only-changed', lockpath, 'packs/basic', 'out', 'NORMAL']));
+    assert.strictEqual(result.status, 0, result.stderr);
+    assert.match(result.stdout, /^Changed, rebuilding: basic--alice--Morning$/m);
+    assert.match(result.stdout, /^Unchanged, skipping: basic--bob--Lake$/m);
+    let desktop = fs.readFileSync(path.join(packroot, 'out/usr/share/wallpapers/basic--alice--Morning/metadata.desktop')).toString();
+    assert.match(desktop, /^X-KDE-PluginInfo-License=CC-BY-SA-4.0$/m);
+});
+
+test('watch mode carries me.json edits into metadata.desktop', { timeout: 30000 }, async function (t) {
+    let packroot = copyFixtures(t);
+    let child = require('child_process').spawn(process.execPath, [SCRIPT].concat(TINY, ['--watch', 'packs/basic', 'out', 'NORMAL']), { cwd: packroot });
+    t.after(function () {
+        child.kill();
+    });
+    let stdout = '';
+    // Resolves once the watcher has finished its count-th build
+    const builds = function (count) {
+        return new Promise(function (resolve) {
+            const check = function () {
+                if (stdout.split('[watch] Waiting for changes...').length > count) {
+                    child.stdout.off('data', check);
+                    resolve();
+                };
+            };
+            child.stdout.on('data', check);
+            check();
+        });
+    };
+    child.stdout.on('data', function (chunk) {
+        stdout += chunk;
+    });
+    await builds(1);
+    let metapath = path.join(packroot, 'contributors/alice/me.json');
+    let userdata = JSON.parse(fs.readFileSync(metapath));
+    userdata.license = 'CC-BY-SA-4.0';
+    fs.writeFileSync(metapath, JSON.stringify(userdata));
+    await builds(2);
+    let desktop = fs.readFileSync(path.join(packroot, 'out/usr/share/wallpapers/basic--alice--Morning/metadata.desktop')).toString();
+    assert.match(desktop, /^X-KDE-PluginInfo-License=CC-BY-SA-4.0$/m);
+});
+
+test('paths and geometries reach convert as single arguments', function (t) {
+    let packroot = copyFixtures(t);
+    let im = fakeImageMagick(t);
+    let result = runCli(packroot, TINY.concat(['--imagemagick', im.convert, '--retro-depth', '24', 'packs/basic', 'out', 'RETRO']));
+    assert.strictEqual(result.status, 0, result.stderr);
+    let run = im.runs().find(function (x) { return x[0] === path.join(packroot, 'contributors/alice/1.png') });
+    assert.ok(run, result.stdout);
+    assert.deepStrictEqual(run.slice(1, 3), ['-resize', '800x600']);
+    assert.strictEqual(run[run.length - 1], 'PNG24:out/usr/share/wallpapers/basic--alice--Morning/contents/images/800x600.png');
+    assert.ok(fs.existsSync(path.join(packroot, 'out/usr/share/wallpapers/basic--alice--Morning/contents/images/800x600.png')));
+});
+
+test('--auto-orient copies upright images and converts the others', function (t) {
+    [['TopLeft', false], ['Undefined', false], ['RightTop', true]].forEach(function (x) {
+        let packroot = copyFixtures(t);
+        let im = fakeImageMagick(t, { orientation: x[0] });
+        let result = runCli(packroot, TINY.concat(['--imagemagick', im.convert, '--auto-orient', 'packs/basic', 'out', 'NORMAL']));
+        assert.strictEqual(result.status, 0, result.stderr);
+        let srcpath = path.join(packroot, 'contributors/alice/1.png');
+        let run = im.runs().find(function (y) { return y[0] === srcpath });
+        assert.strictEqual(run !== undefined, x[1], x[0]);
+        if (run) {
+            assert.deepStrictEqual(run.slice(1).map(path.normalize), ['-auto-orient', 'out/usr/share/backgrounds/basic--alice--Morning/basic--alice--Morning.png']);
+        };
+    });
+});
+
+test('--auto-orient turns pixels the way the EXIF orientation says', function (t) {
+    if (spawn('convert', ['-version']).error) {
+        t.skip('ImageMagick is not installed');
+        return;
+    };
+    let packroot = copyFixtures(t);
+    // Red on the left, blue on the right, tagged to be shown turned clockwise
+    let srcpath = path.join(packroot, 'contributors/alice/1.jpg');
+    assert.strictEqual(spawn('convert', [path.join(packroot, 'contributors/alice/1.png'), '-quality', '100', srcpath]).status, 0);
+    let exif = Buffer.from([
+        0xFF, 0xE1, 0x00, 0x22, 0x45, 0x78, 0x69, 0x66, 0x00, 0x00,
+        // Big-endian TIFF header, then one IFD entry: Orientation (0x0112), SHORT, 1 value, 6
+        0x4D, 0x4D, 0x00, 0x2A, 0x00, 0x00, 0x00, 0x08, 0x00, 0x01,
+        0x01, 0x12, 0x00, 0x03, 0x00, 0x00, 0x00, 0x01, 0x00, 0x06, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00
+    ]);
+    let jpeg = fs.readFileSync(srcpath);
+    fs.writeFileSync(srcpath, Buffer.concat([jpeg.subarray(0, 2), exif, jpeg.subarray(2)]));
+    assert.strictEqual(spawn('identify', ['-format', '%[orientation]', srcpath], { encoding: 'utf8' }).stdout, 'RightTop');
+    let metapath = path.join(packroot, 'contributors/alice/me.json');
+    let userdata = JSON.parse(fs.readFileSync(metapath));
+    userdata.wallpapers[0].f = 'jpg';
+    fs.writeFileSync(metapath, JSON.stringify(userdata));
+    let result = runCli(packroot, TINY.concat(['--auto-orient', 'packs/basic', 'out', 'NORMAL']));
+    assert.strictEqual(result.status, 0, result.stderr);
+    let outpath = path.join(packroot, 'out/usr/share/backgrounds/basic--alice--Morning/basic--alice--Morning.jpg');
+    const pixel = function (x, y) {
+        return spawn('convert', [outpath, '-format', `%[fx:round(255*p{${x},${y}}.r)] %[fx:round(255*p{${x},${y}}.b)]`, 'info:'], { encoding: 'utf8' }).stdout.split(' ').map(Number);
+    };
+    assert.strictEqual(spawn('identify', ['-format', '%w %h %[orientation]', outpath], { encoding: 'utf8' }).stdout, '4 8 TopLeft');
+    // Turned clockwise, the left half ends up on top
+    assert.ok(pixel(2, 1)[0] > 200 && pixel(2, 1)[1] < 60, String(pixel(2, 1)));
+    assert.ok(pixel(2, 6)[0] < 60 && pixel(2, 6)[1] > 200, String(pixel(2, 6)));
+});
+
+test('--to-srgb copies untagged and sRGB images and converts the others', function (t) {
+    [['', false], ['sRGB IEC61966-2.1', false], ['Display P3', true]].forEach(function (x) {
+        let packroot = copyFixtures(t);
+        let im = fakeImageMagick(t, { profile: x[0] });
+        let result = runCli(packroot, TINY.concat(['--imagemagick', im.convert, '--to-srgb', '--srgb-profile', 'sRGB.icc', 'packs/basic', 'out', 'NORMAL']));
+        assert.strictEqual(result.status, 0, result.stderr);
+        let srcpath = path.join(packroot, 'contributors/alice/1.png');
+        let run = im.runs().find(function (y) { return y[0] === srcpath });
+        assert.strictEqual(run !== undefined, x[1], x[0]);
+        if (run) {
+            assert.deepStrictEqual(run.slice(1).map(path.normalize), ['-profile', 'sRGB.icc', 'out/usr/share/backgrounds/basic--alice--Morning/basic--alice--Morning.png']);
+        };
+    });
+});
+
+test('--lint-manifest reports deprecated selections unless they are included', function (t) {
+    let packroot = copyFixtures(t);
+    let result = runCli(packroot, ['--lint-manifest', 'packs/deprecated']);
+    assert.strictEqual(result.status, 1);
+    assert.match(result.stderr, /Wallpaper 2 of "alice" is deprecated/);
+    result = runCli(packroot, ['--include-deprecated', '--lint-manifest', 'packs/deprecated']);
+    assert.strictEqual(result.status, 0, result.stderr);
+    assert.match(result.stdout, /selecting 2 wallpapers/);
+});
+
+test('--route-tag is rejected with bundles that would hold only the default tree', function (t) {
+    let packroot = copyFixtures(t);
+    let result = runCli(packroot, TINY.concat(['--route-tag', 'nature=out-nature', '--gresource', 'pack.gresource', 'packs/basic', 'out', 'NORMAL']));
+    assert.strictEqual(result.status, 1);
+    assert.match(result.stderr, /--route-tag and --gresource cannot be used together/);
+    assert.ok(!fs.existsSync(path.join(packroot, 'out')));
+    result = runCli(packroot, TINY.concat(['--route-tag', 'nature=out-nature', '--squashfs', 'pack.squashfs', 'packs/basic', 'out', 'NORMAL']));
+    assert.strictEqual(result.status, 1);
+    assert.match(result.stderr, /--route-tag and --squashfs cannot be used together/);
+    assert.ok(!fs.existsSync(path.join(packroot, 'out')));
+});
+
+test('license overrides are told under --verbose only, and only when the license differs', function (t) {
+    let packroot = copyFixtures(t);
+    let metapath = path.join(packroot, 'contributors/alice/me.json');
+    let userdata = JSON.parse(fs.readFileSync(metapath));
+    userdata.wallpapers[0].license = 'CC BY 4.0';
+    fs.writeFileSync(metapath, JSON.stringify(userdata));
+    let result = runCli(packroot, TINY.concat(['--dry-run', 'packs/basic', 'out', 'NORMAL']));
+    assert.strictEqual(result.status, 0, result.stderr);
+    assert.doesNotMatch(result.stdout, /overrides default license/);
+    result = runCli(packroot, TINY.concat(['--verbose', '--dry-run', 'packs/basic', 'out', 'NORMAL']));
+    assert.strictEqual(result.status, 0, result.stderr);
+    assert.doesNotMatch(result.stdout, /"Morning" of "alice" overrides/);
+    assert.match(result.stdout, /"Sunset" of "alice" overrides default license "CC-BY-4.0" with "CC0-1.0"/);
+});
+
+test('--size-budget counts metadata and albums along with the images', function (t) {
+    let packroot = copyFixtures(t);
+    let images = ['contributors/alice/1.png', 'contributors/alice/2.png', 'contributors/bob/1.png'].reduce(function (sum, relpath) {
+        return sum + fs.statSync(path.join(packroot, relpath)).size;
+    }, 0);
+    [['--dry-run'], []].forEach(function (extra) {
+        let result = runCli(packroot, TINY.concat(extra, ['--size-budget', String(images), 'packs/basic', 'out', 'NORMAL']));
+        assert.strictEqual(result.status, 1, extra.join(' '));
+        assert.match(result.stderr, new RegExp(`over the budget of ${images} B`));
+    });
+});
+
+test('a uname which is not a plain name is rejected before anything runs', function (t) {
+    let packroot = copyFixtures(t);
+    let metapath = path.join(packroot, 'contributors/alice/me.json');
+    let userdata = JSON.parse(fs.readFileSync(metapath));
+    userdata.uname = 'x;touch${IFS}PWNED;';
+    fs.writeFileSync(metapath, JSON.stringify(userdata));
+    let result = runCli(packroot, TINY.concat(['--dry-run', 'packs/basic', 'out', 'RETRO']));
+    assert.strictEqual(result.status, 1);
+    assert.match(result.stderr, /has bad uname/);
+    assert.ok(!fs.existsSync(path.join(packroot, 'PWNED')));
+});
+
+test('--thumbnails-only writes thumbnails and leaves dist and db.json alone', function (t) {
+    let packroot = copyFixtures(t);
+    let im = fakeImageMagick(t);
+    let result = runCli(packroot, TINY.concat(['--imagemagick', im.convert, '--thumbnails-only', 'thumbs', 'packs/basic', 'out', 'NORMAL']));
+    assert.strictEqual(result.status, 0, result.stderr);
+    assert.deepStrictEqual(fs.readdirSync(path.join(packroot, 'thumbs')).sort(), ['basic--alice--Morning.jpg', 'basic--alice--Sunset.jpg', 'basic--bob--Lake.jpg']);
+    assert.ok(!fs.existsSync(path.join(packroot, 'dist')));
+    assert.ok(!fs.existsSync(path.join(packroot, 'db.json')));
+    assert.ok(!fs.existsSync(path.join(packroot, 'out')));
+});
+
+test('dist copies all finish before a failing build exits', function (t) {
+    let packroot = copyFixtures(t);
+    let result = runCli(packroot, TINY.concat(['--copy-concurrency', '1', '--size-budget', '1', 'packs/basic', 'out', 'NORMAL']));
+    assert.strictEqual(result.status, 1);
+    assert.match(result.stderr, /over the budget/);
+    [['Morning.png', 'alice/1.png'], ['Sunset.png', 'alice/2.png'], ['Lake.png', 'bob/1.png']].forEach(function (x) {
+        assert.deepStrictEqual(fs.readFileSync(path.join(packroot, 'dist', x[0])), fs.readFileSync(path.join(FIXTURES, 'contributors', x[1])));
+    });
+});
+
+test('--backend-fallback retries a failed conversion with the other convert', function (t) {
+    let packroot = copyFixtures(t);
+    let primary = fakeImageMagick(t, { fail: true });
+    let fallback = fakeImageMagick(t);
+    let result = runCli(packroot, TINY.concat(['--imagemagick', primary.convert, '--backend-fallback', fallback.convert, '--retro-depth', '24', 'packs/basic', 'out', 'RETRO']));
+    assert.strictEqual(result.status, 0, result.stderr);
+    assert.strictEqual(primary.runs().length, fallback.runs().length);
+    assert.ok(fallback.runs().length > 0);
+    assert.ok(fs.existsSync(path.join(packroot, 'out/usr/share/wallpapers/basic--alice--Morning/contents/images/800x600.png')));
+});
+
+test('--backend-fallback is looked up without a shell', function (t) {
+    let packroot = copyFixtures(t);
+    let result = runCli(packroot, TINY.concat(['--backend-fallback', 'no-such-convert;touch${IFS}PWNED convert', 'packs/basic', 'out', 'NORMAL']));
+    assert.strictEqual(result.status, 1);
+    assert.match(result.stderr, /no-such-convert;touch\$\{IFS\}PWNED is not installed/);
+    assert.ok(!fs.existsSync(path.join(packroot, 'PWNED')));
+});
+
+test('--route-tag with an empty tag or directory is rejected', function (t) {
+    let packroot = copyFixtures(t);
+    [' =o3', 'nature= ', 'nature=/'].forEach(function (route) {
+        let result = runCli(packroot, TINY.concat(['--route-tag', route, 'packs/basic', 'out', 'NORMAL']));
+        assert.strictEqual(result.status, 1, route);
+        assert.match(result.stderr, /^Error: Bad --route-tag/m, route);
+    });
+});
+
+test('a CSV manifest with extra columns resolves like the text manifest', function (t) {
+    let packroot = copyFixtures(t);
+    fs.writeFileSync(path.join(packroot, 'packs/basic.csv'), [
+        'Artist,Notes,Index,Approved',
+        'alice,"warm, bright",0,yes',
+        'alice,"a note',
+        'over two lines",1,yes',
+        'alice,,2,no',
+        'bob,,0,approved',
+        ''
+    ].join('\r\n'));
+    const resolved = function (args) {
+        let result = runCli(packroot, TINY.concat(['--print-resolved', '--dry-run', '--quiet'], args, ['out', 'NORMAL']));
+        assert.strictEqual(result.status, 0, result.stderr);
+        return result.stdout.split('\n').filter(function (line) { return line.match(/^\w+:\d+$/) });
+    };
+    let fromCsv = resolved(['--manifest-format', 'csv', '--csv-approval-col', 'approved', 'packs/basic.csv']);
+    assert.deepStrictEqual(fromCsv, ['alice:0', 'alice:1', 'bob:0']);
+    assert.deepStrictEqual(fromCsv, resolved(['packs/basic']));
+});
+
+test('CSV manifest errors name the spreadsheet row, and an empty CSV is reported', function (t) {
+    let packroot = copyFixtures(t);
+    fs.writeFileSync(path.join(packroot, 'packs/bad.csv'), 'artist,notes,index\nalice,"two\nlines",0\n\ncarol,,0\n');
+    let result = runCli(packroot, TINY.concat(['--manifest-format', 'csv', 'packs/bad.csv', 'out', 'NORMAL']));
+    assert.strictEqual(result.status, 1);
+    assert.match(result.stderr, /Line 5 selects unknown contributor "carol"/);
+    fs.writeFileSync(path.join(packroot, 'packs/empty.csv'), '');
+    result = runCli(packroot, TINY.concat(['--manifest-format', 'csv', 'packs/empty.csv', 'out', 'NORMAL']));
+    assert.strictEqual(result.status, 1);
+    assert.match(result.stderr, /^Error: "packs\/empty\.csv" is an empty CSV manifest\.$/m);
+});
+
+test('--migrate-metadata upgrades short keys, and both schemas build the same', function (t) {
+    let packroot = copyFixtures(t);
+    let metapath = path.join(packroot, 'contributors/bob/me.json');
+    let userdata = JSON.parse(fs.readFileSync(metapath, 'utf8'));
+    // The short-key schema, with keys in no particular order
+    let shortdata = {
+        wallpapers: userdata.wallpapers.map(function (x) {
+            return { f: x.format, tags: x.tags, i: x.index, t: x.title };
+        }),
+        license: userdata.license,
+        email: userdata.email,
+        uri: userdata.uri,
+        name: userdata.name,
+        uname: userdata.uname
+    };
+    const build = function () {
+        let result = runCli(packroot, TINY.concat(['packs/basic', 'out', 'NORMAL']));
+        assert.strictEqual(result.status, 0, result.stderr);
+        return fs.readFileSync(path.join(packroot, 'out/usr/share/wallpapers/basic--bob--Lake/metadata.desktop'), 'utf8');
+    };
+    let fromLong = build();
+    fs.writeFileSync(metapath, JSON.stringify(shortdata));
+    assert.strictEqual(build(), fromLong);
+    let result = runCli(packroot, ['--migrate-metadata', 'contributors/bob']);
+    assert.strictEqual(result.status, 0, result.stderr);
+    assert.match(result.stdout, /Renamed "t" to "title" in 1 wallpapers\./);
+    // Same keys in the same order as the long-key fixture
+    assert.strictEqual(JSON.stringify(JSON.parse(fs.readFileSync(metapath, 'utf8'))), JSON.stringify(userdata));
+    result = runCli(packroot, ['--migrate-metadata', 'contributors/bob']);
+    assert.match(result.stdout, /Already in the newest schema/);
+    assert.strictEqual(build(), fromLong);
+});
+
+test('a display name names the slideshow, and album files keep the pack name', function (t) {
+    let packroot = copyFixtures(t);
+    let result = runCli(packroot, TINY.concat(['--display-name', '夏天', '--slideshow', '60', 'packs/basic', 'out', 'NORMAL']));
+    assert.strictEqual(result.status, 0, result.stderr);
+    let album = fs.readFileSync(path.join(packroot, 'out/usr/share/background-properties/Basic.xml')).toString();
+    assert.match(album, /<name>夏天 Slideshow<\/name>/);
+    fs.writeFileSync(path.join(packroot, 'packs/basic.pack.json'), JSON.stringify({ name: 'Été' }));
+    result = runCli(packroot, TINY.concat(['packs/basic', 'out2', 'NORMAL']));
+    assert.strictEqual(result.status, 0, result.stderr);
+    assert.deepStrictEqual(fs.readdirSync(path.join(packroot, 'out2/usr/share/background-properties')), ['Basic.xml']);
+    assert.strictEqual(wallcolle.getAlbumName('été'), 'Été');
+});
+
+test('--dedupe-symlinks names the link after the size left by --max-resolution', function (t) {
+    let packroot = copyFixtures(t);
+    let im = fakeImageMagick(t);
+    let result = runCli(packroot, TINY.concat(['--imagemagick', im.convert, '--max-resolution', '4x4', '--dedupe-symlinks', 'packs/basic', 'out', 'NORMAL']));
+    assert.strictEqual(result.status, 0, result.stderr);
+    let images = path.join(packroot, 'out/usr/share/wallpapers/basic--alice--Morning/contents/images');
+    assert.deepStrictEqual(fs.readdirSync(images), ['4x2.png']);
+});
+
+test('checksums are written next to the tree, with paths relative to it', function (t) {
+    let packroot = copyFixtures(t);
+    let result = runCli(packroot, TINY.concat(['--checksums', '--checksums-per-artist', 'packs/basic', 'out', 'NORMAL']));
+    assert.strictEqual(result.status, 0, result.stderr);
+    assert.deepStrictEqual(fs.readdirSync(path.join(packroot, 'out')), ['usr']);
+    let sums = fs.readFileSync(path.join(packroot, 'out.SHA256SUMS'), 'utf8');
+    assert.match(sums, /^[0-9a-f]{64} {2}usr\/share\/backgrounds\/basic--bob--Lake\/basic--bob--Lake\.png$/m);
+    assert.ok(fs.existsSync(path.join(packroot, 'out.SHA256SUMS.alice')));
+    result = runCli(packroot, TINY.concat(['--checksums', '--checksums-dir', 'sums', 'packs/basic', 'out', 'NORMAL']));
+    assert.strictEqual(result.status, 0, result.stderr);
+    assert.strictEqual(fs.readFileSync(path.join(packroot, 'sums/out.SHA256SUMS'), 'utf8'), sums);
+});
+
+test('a me.toml without its parser installed asks for the parser', function (t) {
+    try {
+        require.resolve('@iarna/toml');
+        t.skip('@iarna/toml is installed');
+        return;
+    } catch (e) {
+    };
+    let packroot = copyFixtures(t);
+    fs.renameSync(path.join(packroot, 'contributors/bob/me.json'), path.join(packroot, 'contributors/bob/me.toml'));
+    let result = runCli(packroot, TINY.concat(['packs/basic', 'out', 'NORMAL']));
+    assert.strictEqual(result.status, 1);
+    assert.match(result.stderr, /^Error: Install @iarna\/toml to read ".*contributors\/bob\/me\.toml"/m);
+});
+
+test('entry names only have to be unique within the selection', function (t) {
+    let packroot = copyFixtures(t);
+    let metapath = path.join(packroot, 'contributors/alice/me.json');
+    let meta = JSON.parse(fs.readFileSync(metapath, 'utf8'));
+    meta.wallpapers[2].title = 'Morning';
+    fs.writeFileSync(metapath, JSON.stringify(meta));
+    let result = runCli(packroot, TINY.concat(['packs/basic', 'out', 'NORMAL']));
+    assert.strictEqual(result.status, 0, result.stderr);
+    result = runCli(packroot, TINY.concat(['--include-deprecated', 'packs/deprecated', 'out2', 'NORMAL']));
+    assert.strictEqual(result.status, 1);
+    assert.match(result.stderr, /^Error: Wallpapers "Morning" and "Morning" of "alice" share the entry name "deprecated--alice--Morning"\.$/m);
+    assert.ok(!fs.existsSync(path.join(packroot, 'out2')));
+});
+
+test('a directory where a link goes is left alone and fails the build', function (t) {
+    let packroot = copyFixtures(t);
+    let inTheWay = path.join(packroot, 'out/usr/share/wallpapers/basic--alice--Morning/screenshot.png');
+    fs.mkdirSync(inTheWay, { recursive: true });
+    fs.writeFileSync(path.join(inTheWay, 'keep'), '');
+    let result = runCli(packroot, TINY.concat(['packs/basic', 'out', 'NORMAL']));
+    assert.strictEqual(result.status, 1);
+    assert.match(result.stderr, /Cannot link ".*screenshot\.png", a directory is in the way\./);
+    assert.ok(fs.existsSync(path.join(inTheWay, 'keep')));
+});
+
+test('crops are held against the blocklist like the main image', function (t) {
+    let packroot = copyFixtures(t);
+    let bobdir = path.join(packroot, 'contributors/bob');
+    let meta = JSON.parse(fs.readFileSync(path.join(bobdir, 'me.json'), 'utf8'));
+    meta.wallpapers[0].crops = [{ i: 2, f: 'png', aspect: '16:9' }];
+    fs.writeFileSync(path.join(bobdir, 'me.json'), JSON.stringify(meta));
+    fs.copyFileSync(path.join(packroot, 'contributors/alice/3.png'), path.join(bobdir, '2.png'));
+    let digest = require('crypto').createHash('sha256').update(fs.readFileSync(path.join(bobdir, '2.png'))).digest('hex');
+    fs.writeFileSync(path.join(packroot, 'blocklist'), `${digest}  bob/2.png\n`);
+    let result = runCli(packroot, TINY.concat(['--blocklist', 'blocklist', 'packs/basic', 'out', 'NORMAL']));
+    assert.strictEqual(result.status, 1);
+    assert.match(result.stderr, /^Error: Wallpaper "Lake" of "bob" is blocklisted \(.*contributors\/bob\/2\.png\)\.$/m);
+});
+
+test('--update-checksums reads formats as the build does', function (t) {
+    let packroot = copyFixtures(t);
+    let metapath = path.join(packroot, 'contributors/bob/me.json');
+    let meta = JSON.parse(fs.readFileSync(metapath, 'utf8'));
+    meta.wallpapers[0].format = ' PNG ';
+    fs.writeFileSync(metapath, JSON.stringify(meta));
+    let result = runCli(packroot, ['--update-checksums']);
+    assert.strictEqual(result.status, 0, result.stderr);
+    assert.doesNotMatch(result.stderr, /bob/);
+    let digest = require('crypto').createHash('sha256').update(fs.readFileSync(path.join(packroot, 'contributors/bob/1.png'))).digest('hex');
+    assert.strictEqual(JSON.parse(fs.readFileSync(metapath, 'utf8')).wallpapers[0].sha256, digest);
+    result = runCli(packroot, TINY.concat(['packs/basic', 'out', 'NORMAL']));
+    assert.strictEqual(result.status, 0, result.stderr);
+});
+
+test('--validate-desktop runs desktop-file-validate on escaped entries and warns of failures', function (t) {
+    let packroot = copyFixtures(t);
+    let metapath = path.join(packroot, 'contributors/bob/me.json');
+    let meta = JSON.parse(fs.readFileSync(metapath, 'utf8'));
+    Object.assign(meta.wallpapers[0], { title: 'Lake = Blue; Été', slug: 'Lake', tags: ['nature', 'a;b'] });
+    fs.writeFileSync(metapath, JSON.stringify(meta));
+    let validator = fakeTool(t, 'desktop-file-validate', [
+        'printf \'%s\\n\' "$1" >> "$LOG"',
+        'if grep -q Blue "$1"; then echo "$1: error: fake complaint"; exit 1; fi'
+    ]);
+    let result = runCli(packroot, TINY.concat(['--validate-desktop', 'packs/basic', 'out', 'NORMAL']), validator.env);
+    assert.strictEqual(result.status, 0, result.stderr);
+    assert.strictEqual(validator.logged().length, 3);
+    let desktop = fs.readFileSync(path.join(packroot, 'out/usr/share/wallpapers/basic--bob--Lake/metadata.desktop'), 'utf8');
+    assert.match(desktop, /^Type=Service$/m);
+    assert.match(desktop, /^Name=Lake = Blue; Été$/m);
+    assert.match(desktop, /^X-KDE-Keywords=nature;a\\;b;$/m);
+    assert.match(result.stderr, /^Warning: .*basic--bob--Lake\/metadata\.desktop did not validate: .*error: fake complaint$/m);
+    assert.doesNotMatch(result.stderr, /basic--alice--Morning\/metadata\.desktop did not validate/);
+});
+
+test('--convert-timeout kills a hung convert and fails its wallpaper', function (t) {
+    let packroot = copyFixtures(t);
+    let im = fakeImageMagick(t, { hang: 30 });
+    let started = Date.now();
+    let result = runCli(packroot, TINY.concat(['--imagemagick', im.convert, '--convert-timeout', '0.5', '--keep-going', '--report', 'report.json', '--retro-depth', '24', 'packs/basic', 'out', 'RETRO']));
+    assert.ok(Date.now() - started < 20000, 'expecting the hung runs to be killed');
+    assert.strictEqual(result.status, 1);
+    assert.match(result.stderr, /^Error: Failed to process "basic--alice--Morning" .*Killed after 0\.5 seconds: /m);
+    let report = JSON.parse(fs.readFileSync(path.join(packroot, 'report.json'), 'utf8'));
+    // --keep-going carries on with the next wallpapers, which hang as well
+    assert.deepStrictEqual(report.processed.map(function (x) { return x.ok }), [false, false, false]);
+});
+
+test('--squashfs packs the built tree with mksquashfs, keeping links as they are', function (t) {
+    let packroot = copyFixtures(t);
+    let mksquashfs = fakeTool(t, 'mksquashfs', [
+        'printf \'%s\\n\' "$@" >> "$LOG"',
+        'test -L "$1/usr/share/gnome-background-properties/Basic.xml" || exit 1',
+        ': > "$2"'
+    ]);
+    let result = runCli(packroot, TINY.concat(['--squashfs', 'basic.sqfs', '--reproducible', '--squashfs-remove-tree', 'packs/basic', 'out', 'NORMAL']), mksquashfs.env);
+    assert.strictEqual(result.status, 0, result.stderr);
+    assert.deepStrictEqual(mksquashfs.logged(), ['out', 'basic.sqfs', '-noappend', '-all-root', '-mkfs-time', '0', '-all-time', '0']);
+    assert.ok(fs.existsSync(path.join(packroot, 'basic.sqfs')));
+    assert.ok(!fs.existsSync(path.join(packroot, 'out')));
+});
+
+test('--squashfs needs mksquashfs up front', function (t) {
+    let packroot = copyFixtures(t);
+    let result = runCli(packroot, TINY.concat(['--squashfs', 'basic.sqfs', 'packs/basic', 'out', 'NORMAL']), { PATH: '/nonexistent' });
+    assert.strictEqual(result.status, 1);
+    assert.match(result.stderr, /^Error: mksquashfs is not installed, but --squashfs needs it\.$/m);
+    assert.ok(!fs.existsSync(path.join(packroot, 'out')));
+});
+
+test('album links are relative, and a rebuild under a new name clears the links of the old album', function (t) {
+    let packroot = copyFixtures(t);
+    let result = runCli(packroot, TINY.concat(['packs/basic', 'out', 'NORMAL']));
+    assert.strictEqual(result.status, 0, result.stderr);
+    // Rebuilding over the same tree keeps the link in place
+    result = runCli(packroot, TINY.concat(['packs/basic', 'out', 'NORMAL']));
+    assert.strictEqual(result.status, 0, result.stderr);
+    fs.renameSync(path.join(packroot, 'packs/basic'), path.join(packroot, 'packs/renamed'));
+    fs.rmSync(path.join(packroot, 'out/usr/share/background-properties/Basic.xml'));
+    result = runCli(packroot, TINY.concat(['packs/renamed', 'out', 'NORMAL']));
+    assert.strictEqual(result.status, 0, result.stderr);
+    ['gnome', 'mate'].forEach(function (desktop) {
+        let linkdir = path.join(packroot, `out/usr/share/${desktop}-background-properties`);
+        assert.deepStrictEqual(fs.readdirSync(linkdir), ['Renamed.xml']);
+        assert.strictEqual(fs.readlinkSync(path.join(linkdir, 'Renamed.xml')), '../background-properties/Renamed.xml');
+        assert.ok(fs.existsSync(path.join(linkdir, 'Renamed.xml')));
+    });
+});
+
+test('--pack-url builds from a pack root fetched over HTTP, and removes it afterwards', async function (t) {
+    let packroot = copyFixtures(t);
+    let served = fs.mkdtempSync(path.join(os.tmpdir(), 'wallcolle-served-'));
+    t.after(function () {
+        fs.rmSync(served, { recursive: true, force: true });
+    });
+    // A single top-level directory, as release tarballs have
+    let tar = spawn('tar', ['-cf', path.join(served, 'pack.tar'), '-C', path.dirname(packroot), path.basename(packroot)]);
+    assert.strictEqual(tar.status, 0, String(tar.stderr));
+    let url = await serveDirectory(t, served);
+    // Nothing of the pack is found where the build runs
+    let workdir = path.join(served, 'work');
+    fs.mkdirSync(workdir);
+    const extracted = function () {
+        return fs.readdirSync(os.tmpdir()).filter(function (x) { return x.indexOf('wallcolle-pack-') === 0 }).length;
+    };
+    let before = extracted();
+    let result = runCli(workdir, TINY.concat(['--pack-url', `${url}/pack.tar`, 'packs/basic', 'out', 'NORMAL']));
+    assert.strictEqual(result.status, 0, result.stderr);
+    assert.ok(fs.existsSync(path.join(workdir, 'out/usr/share/wallpapers/basic--bob--Lake/metadata.desktop')));
+    assert.strictEqual(extracted(), before);
+    result = runCli(workdir, TINY.concat(['--pack-url', `${url}/missing.tar`, 'packs/basic', 'out2', 'NORMAL']));
+    assert.strictEqual(result.status, 1);
+    assert.match(result.stderr, /^Error: Cannot fetch pack from ".*\/missing\.tar", curl failed with status 22\.$/m);
+});
+
+test('--atomic leaves the destination untouched when the build fails', function (t) {
+    let packroot = copyFixtures(t);
+    let result = runCli(packroot, TINY.concat(['packs/basic', 'out', 'NORMAL']));
+    assert.strictEqual(result.status, 0, result.stderr);
+    // Every path of the tree, with where links point and what files hold
+    const snapshot = function (dirpath) {
+        return fs.readdirSync(dirpath, { recursive: true }).sort().map(function (relpath) {
+            let anypath = path.join(dirpath, relpath);
+            let stat = fs.lstatSync(anypath);
+            return [relpath, stat.isSymbolicLink() ? fs.readlinkSync(anypath) : stat.isFile() ? fs.readFileSync(anypath, 'base64') : null];
+        });
+    };
+    let before = snapshot(path.join(packroot, 'out'));
+    let im = fakeImageMagick(t, { fail: true });
+    result = runCli(packroot, TINY.concat(['--imagemagick', im.convert, '--atomic', '--keep-going', '--retro-depth', '24', 'packs/basic', 'out', 'RETRO']));
+    assert.strictEqual(result.status, 1);
+    assert.match(result.stdout, /^Build failed, leaving the destination as it was\.$/m);
+    assert.deepStrictEqual(snapshot(path.join(packroot, 'out')), before);
+    assert.deepStrictEqual(fs.readdirSync(packroot).filter(function (x) { return x.indexOf('out.') === 0 }), []);
+});
+
+test('GraphicsMagick and outdated ImageMagick are turned away before anything is written', function (t) {
+    let packroot = copyFixtures(t);
+    let gm = fakeImageMagick(t, { version: 'GraphicsMagick 1.3.38 2022-03-26 Q16 http://www.GraphicsMagick.org/' });
+    let result = runCli(packroot, TINY.concat(['--imagemagick', gm.convert, 'packs/basic', 'out', 'RETRO']));
+    assert.strictEqual(result.status, 1);
+    assert.match(result.stderr, /^Error: .*convert is GraphicsMagick \("GraphicsMagick 1\.3\.38 2022-03-26 Q16"\), not ImageMagick/m);
+    assert.match(result.stderr, /point --imagemagick at its convert or magick binary/);
+    let old = fakeImageMagick(t, { version: 'Version: ImageMagick 6.8.9-9 Q16 x86_64 2019-11-12 https://legacy.imagemagick.org' });
+    result = runCli(packroot, TINY.concat(['--imagemagick', old.convert, 'packs/basic', 'out', 'RETRO']));
+    assert.strictEqual(result.status, 1);
+    assert.match(result.stderr, /^Error: .*convert is ImageMagick 6\.8\.9-9 Q16 x86_64 2019-11-12, older than the supported minimum ImageMagick 6\.9\.0\.$/m);
+    assert.deepStrictEqual(gm.runs().concat(old.runs()), []);
+    assert.ok(!fs.existsSync(path.join(packroot, 'out')));
+});
+
+test('--manifest-encoding reads a Latin-1 manifest with accented artist names', function (t) {
+    let packroot = copyFixtures(t);
+    let josedir = path.join(packroot, 'contributors/josé');
+    fs.cpSync(path.join(packroot, 'contributors/bob'), josedir, { recursive: true });
+    let meta = JSON.parse(fs.readFileSync(path.join(josedir, 'me.json'), 'utf8'));
+    Object.assign(meta, { uname: 'josé', name: 'José' });
+    fs.writeFileSync(path.join(josedir, 'me.json'), JSON.stringify(meta));
+    fs.writeFileSync(path.join(packroot, 'packs/latin'), Buffer.from('# Café pack\njosé:0\nalice:0\n', 'latin1'));
+    let result = runCli(packroot, TINY.concat(['packs/latin', 'out', 'NORMAL']));
+    assert.strictEqual(result.status, 1);
+    assert.match(result.stderr, /^Error: "packs\/latin" is not valid utf-8, set its encoding with --manifest-encoding\.$/m);
+    result = runCli(packroot, TINY.concat(['--manifest-encoding', 'latin-1', 'packs/latin', 'out', 'NORMAL']));
+    assert.strictEqual(result.status, 0, result.stderr);
+    assert.ok(fs.existsSync(path.join(packroot, 'out/usr/share/wallpapers/latin--josé--Lake/metadata.desktop')));
+    let manifest = fs.readFileSync(path.join(packroot, 'dist/manifest.txt'), 'utf8');
+    assert.match(manifest, /^# {5}Café pack$/m);
+    assert.match(manifest, /^Lake {32}José {16}CC0-1\.0$/m);
+});
+
+test('contributor directories stored in NFD are matched by NFC manifest names', function (t) {
+    let packroot = copyFixtures(t);
+    let josedir = path.join(packroot, 'contributors', 'josé'.normalize('NFD'));
+    fs.cpSync(path.join(packroot, 'contributors/bob'), josedir, { recursive: true });
+    let meta = JSON.parse(fs.readFileSync(path.join(josedir, 'me.json'), 'utf8'));
+    meta.uname = 'josé'.normalize('NFC');
+    fs.writeFileSync(path.join(josedir, 'me.json'), JSON.stringify(meta));
+    fs.writeFileSync(path.join(packroot, 'packs/nfc'), `${'josé'.normalize('NFC')}:0\n`);
+    fs.writeFileSync(path.join(packroot, 'packs/nfd'), `${'josé'.normalize('NFD')}:0\n`);
+    for (let pack of ['nfc', 'nfd']) {
+        let result = runCli(packroot, TINY.concat([`packs/${pack}`, `out-${pack}`, 'NORMAL']));
+        assert.strictEqual(result.status, 0, result.stderr);
+        assert.doesNotMatch(result.stderr, /not its directory name/);
+        let entries = fs.readdirSync(path.join(packroot, `out-${pack}/usr/share/wallpapers`));
+        assert.deepStrictEqual(entries, [`${pack}--${'josé'.normalize('NFC')}--Lake`]);
+    };
+});
+
+test('--gresource lists the regular files of the tree and compiles them with glib-compile-resources', function (t) {
+    let packroot = copyFixtures(t);
+    let compiler = fakeTool(t, 'glib-compile-resources', [
+        'printf \'%s\\n\' "$@" >> "$LOG"',
+        ': > "${2#--target=}"'
+    ]);
+    let result = runCli(packroot, TINY.concat(['--gresource', 'basic.gresource', '--gresource-prefix', '/org/example/walls', 'packs/basic', 'out', 'NORMAL']), compiler.env);
+    assert.strictEqual(result.status, 0, result.stderr);
+    assert.deepStrictEqual(compiler.logged(), ['--sourcedir=out', '--target=basic.gresource', 'basic.gresource.xml']);
+    assert.ok(fs.existsSync(path.join(packroot, 'basic.gresource')));
+    let manifest = fs.readFileSync(path.join(packroot, 'basic.gresource.xml'), 'utf8');
+    assert.match(manifest, /^ {4}<gresource prefix="\/org\/example\/walls">$/m);
+    assert.match(manifest, /^ {8}<file compressed="true">usr\/share\/wallpapers\/basic--bob--Lake\/metadata\.desktop<\/file>$/m);
+    // The album link cannot go into a bundle
+    assert.doesNotMatch(manifest, /gnome-background-properties\/Basic\.xml/);
+});
+
+test('--gresource needs glib-compile-resources up front', function (t) {
+    let packroot = copyFixtures(t);
+    let result = runCli(packroot, TINY.concat(['--gresource', 'basic.gresource', 'packs/basic', 'out', 'NORMAL']), { PATH: '/nonexistent' });
+    assert.strictEqual(result.status, 1);
+    assert.match(result.stderr, /^Error: glib-compile-resources is not installed, but --gresource needs it\.$/m);
+    assert.ok(!fs.existsSync(path.join(packroot, 'out')));
+});
+
+// --------------------------------------
+// Programmatic use
+
+// The programmatic API reads the pack root from the current directory
+const inPackRoot = function (t) {
+    let packroot = copyFixtures(t);
+    let cwd = process.cwd();
+    process.chdir(packroot);
+    t.after(function () {
+        process.chdir(cwd);
+    });
+    return packroot;
+};
+
+const declarations = function (entries) {
+    return entries.map(function (x) { return `${x.uname}:${x.t}` });
+};
+
+test('resolvePack returns the selected wallpapers only, and leaves the pack root alone', function (t) {
+    let packroot = inPackRoot(t);
+    fs.mkdirSync('dist');
+    fs.writeFileSync('dist/keepme.txt', 'kept');
+    let entries = wallcolle.resolvePack('packs/basic', 'NORMAL', { 'min-source': '1x1' });
+    assert.deepStrictEqual(declarations(entries), ['alice:Morning', 'alice:Sunset', 'bob:Lake']);
+    assert.deepStrictEqual([entries[0].l, entries[1].l, entries[2].tags], ['CC-BY-4.0', 'CC0-1.0', ['nature']]);
+    // Options do not carry over into the next call
+    assert.deepStrictEqual(declarations(wallcolle.resolvePack('packs/basic', 'NORMAL', { 'min-source': '1x1', 'require-tag': ['nature'] })), ['bob:Lake']);
+    assert.deepStrictEqual(declarations(wallcolle.resolvePack('packs/basic', 'NORMAL', { 'min-source': 1 + 'x1' })), ['alice:Morning', 'alice:Sunset', 'bob:Lake']);
+    assert.deepStrictEqual(fs.readdirSync('dist'), ['keepme.txt']);
+    assert.ok(!fs.existsSync(path.join(packroot, 'db.json')));
+});
+
+test('resolvePack throws with the CLI error, and leaves the process running', function (t) {
+    inPackRoot(t);
+    assert.throws(function () {
+        wallcolle.resolvePack('packs/missing', 'NORMAL', {});
+    }, { status: 1, message: /^Error: Cannot make "missing"\.$/ });
+    assert.throws(function () {
+        wallcolle.resolvePack('packs/basic', 'NORMAL', { 'sort-by': 'size' });
+    }, { status: 1, message: /Unknown order "size"/ });
+});
+
+test('buildPack builds the pack and resolves to its report', async function (t) {
+    let packroot = inPackRoot(t);
+    let report = await wallcolle.buildPack('packs/basic', 'out', 'NORMAL', { 'min-source': '1x1', 'copy-concurrency': 2 });
+    assert.deepStrictEqual(report.processed.map(function (x) { return [x.entry_name, x.status] }), [
+        ['basic--alice--Morning', 'built'],
+        ['basic--alice--Sunset', 'built'],
+        ['basic--bob--Lake', 'built']
+    ]);
+    assert.ok(fs.existsSync(path.join(packroot, 'out/usr/share/wallpapers/basic--bob--Lake/metadata.desktop')));
+    assert.deepStrictEqual(fs.readdirSync(path.join(packroot, 'dist')).sort(), ['Lake.png', 'Morning.png', 'Sunset.png', 'manifest.txt']);
+});
+
+test('buildPack keeps the report of a build with failures', async function (t) {
+    let packroot = inPackRoot(t);
+    // A second wallpaper of Bob named like the first cannot be built next to it
+    let metapath = path.join(packroot, 'contributors/bob/me.json');
+    let userdata = JSON.parse(fs.readFileSync(metapath));
+    userdata.wallpapers.push({ title: 'Lake', index: 2, format: 'png' });
+    fs.writeFileSync(metapath, JSON.stringify(userdata));
+    fs.copyFileSync(path.join(packroot, 'contributors/bob/1.png'), path.join(packroot, 'contributors/bob/2.png'));
+    fs.writeFileSync('packs/collide', 'bob:0\nbob:1\n');
+    let err = await wallcolle.buildPack('packs/collide', 'out', 'NORMAL', { 'min-source': '1x1', 'keep-going': true }).then(function () {
+        return null;
+    }, function (e) {
+        return e;
+    });
+    assert.ok(err, 'expecting the build to fail');
+    assert.strictEqual(err.status, 1);
+    assert.match(err.message, /share the entry name "collide--bob--Lake"/);
+    assert.deepStrictEqual(err.report.processed.map(function (x) { return x.ok }), [true, false]);
+});
+
+test('buildPack rejects a build which fails once dist is copied, and the next call still runs', async function (t) {
+    inPackRoot(t);
+    // The size budget is checked after the copies into dist have called back
+    let err = await wallcolle.buildPack('packs/basic', 'out', 'NORMAL', { 'min-source': '1x1', 'size-budget': '1' }).then(function () {
+        return null;
+    }, function (e) {
+        return e;
+    });
+    assert.ok(err, 'expecting the build to fail');
+    assert.strictEqual(err.status, 1);
+    assert.match(err.message, /^Error: Output is .*, over the budget of 1 B/m);
+    assert.strictEqual(err.report.processed.length, 3);
+    assert.strictEqual((await wallcolle.buildPack('packs/basic', 'out', 'NORMAL', { 'min-source': '1x1' })).processed.length, 3);
+});
+
+test('buildPack rejects options of the wrong type, and command line only options', function (t) {
+    inPackRoot(t);
+    assert.throws(function () {
+        wallcolle.buildPack('packs/basic', 'out', 'NORMAL', { 'keep-going': 'yes' });
+    }, TypeError);
+    assert.throws(function () {
+        wallcolle.buildPack('packs/basic', 'out', 'NORMAL', { 'require-tag': 'nature' });
+    }, TypeError);
+    assert.throws(function () {
+        wallcolle.buildPack('packs/basic', 'out', 'NORMAL', { 'slideshow': true });
+    }, TypeError);
+    assert.throws(function () {
+        wallcolle.buildPack('packs/basic', 'out', 'NORMAL', { 'no-such-option': true });
+    }, /Unknown option "no-such-option"/);
+    assert.throws(function () {
+        wallcolle.buildPack('packs/basic', 'out', 'NORMAL', { 'watch': true });
+    }, /Option "watch" is for the command line only/);
+});
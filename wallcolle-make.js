@@ -9,358 +9,3708 @@
 // --------------------------------------
 // Dependencies
 const fs = require('fs');
+const crypto = require('crypto');
+const os = require('os');
+const path = require('path');
 const exec = require('child_process').execSync;
+const execFile = require('child_process').execFileSync;
+const spawn = require('child_process').spawnSync;
+// Optional: js-yaml and @iarna/toml, loaded when a contributor uses me.yaml or me.toml, see package.json
 
 // --------------------------------------
 // Arguments initialization
 const UUID = 'ea9510656e3a43d8b037dd34490ad52f';
-const PACKPATH = process.argv[2];
-const PACKNAME = PACKPATH.split('/').reverse()[0];
-const DESTDIR = process.argv[3];
-const VARIANT = process.argv[4]; // Destination variant, can be NORMAL or RETRO
 
-if (process.argv.length < 5) {
+// Known SPDX license identifiers; anything starting with "LicenseRef-" is accepted as well
+const SPDX_LICENSES = ['CC0-1.0', 'GPL-2.0-only', 'GPL-2.0-or-later', 'GPL-3.0-only', 'GPL-3.0-or-later',
+    'LGPL-2.1-only', 'LGPL-2.1-or-later', 'LGPL-3.0-only', 'LGPL-3.0-or-later', 'AGPL-3.0-only', 'AGPL-3.0-or-later',
+    'FAL-1.3', 'MIT', 'Apache-2.0'].concat(
+    ['BY', 'BY-SA', 'BY-ND', 'BY-NC', 'BY-NC-SA', 'BY-NC-ND'].map(function (kind) {
+        return ['2.0', '2.5', '3.0', '4.0'].map(function (ver) {
+            return `CC-${kind}-${ver}`;
+        });
+    }).flat()
+);
+
+// Canonical pages for known licenses, shown next to license names
+const LICENSE_URLS = Object.assign({
+    'CC0-1.0': 'https://creativecommons.org/publicdomain/zero/1.0/',
+    'FAL-1.3': 'https://artlibre.org/licence/lal/en/',
+    'MIT': 'https://opensource.org/licenses/MIT',
+    'Apache-2.0': 'https://www.apache.org/licenses/LICENSE-2.0'
+}, ...['GPL-2.0', 'GPL-3.0', 'LGPL-2.1', 'LGPL-3.0', 'AGPL-3.0'].map(function (gnu) {
+    let url = `https://www.gnu.org/licenses/${gnu.toLowerCase()}.html`;
+    return { [`${gnu}-only`]: url, [`${gnu}-or-later`]: url };
+}), ...SPDX_LICENSES.filter(function (x) { return x.indexOf('CC-BY') === 0 }).map(function (x) {
+    let parts = x.match(/^CC-(.+)-(\d\.\d)$/);
+    return { [x]: `https://creativecommons.org/licenses/${parts[1].toLowerCase()}/${parts[2]}/` };
+}));
+
+// License pairs which cannot be redistributed together in one package under common readings, for
+// --check-license-compat. A compliance aid, not legal advice
+const LICENSE_CONFLICTS = [
+    { a: /^CC-BY-NC-/, b: /^(GPL|LGPL|AGPL)-/, reason: 'the GNU licenses forbid adding NonCommercial restrictions' },
+    { a: /^CC-BY-NC-/, b: /^(CC-BY-SA-|FAL-)/, reason: 'NonCommercial terms cannot be passed on under free share-alike terms' },
+    { a: /^CC-BY-SA-[23]\./, b: /^GPL-3\.0-/, reason: 'only version 4.0 of CC BY-SA is one-way compatible with GPLv3' },
+    { a: /^CC-BY-SA-/, b: /^CC-BY-NC-SA-/, reason: 'each share-alike license requires derivatives under itself' }
+];
+
+// Pack policy for the smallest landscape source of each variant
+const MIN_SOURCE = {
+    NORMAL: '1920x1080',
+    RETRO: '1600x1200'
+};
+
+// Sections of a build which a wallpaper may opt out of with "exclude_outputs"; gnome covers the MATE links too
+const OUTPUT_TARGETS = ['gnome', 'kde', 'xfce', 'retro'];
+
+// Where resolution files go inside a KDE wallpaper package. Plasma only reads the standard layout;
+// flat suits desktops and tools that just glob the package directory, like wallpaper pickers of tiling WMs
+const KDE_LAYOUTS = {
+    standard: { images: 'contents/images', images_dark: 'contents/images_dark' },
+    flat: { images: '.', images_dark: 'dark' }
+};
+
+// Sizes generated for retro builds, landscape
+const RETRO_RESOLUTIONS = ['800x600', '1280x960', '1600x1200', '1920x1200'];
+
+// Wallpaper maturity ratings, from the most to the least suitable for a default install
+const RATINGS = ['general', 'mature'];
+
+// Oldest ImageMagick known to handle every conversion made here
+const MIN_IMAGEMAGICK = '6.9.0';
+
+// Dithering accepted by --retro-dither, as pngquant arguments; null where ImageMagick reduces the colors instead
+const RETRO_DITHERS = {
+    'none': '--nofs',
+    'floyd-steinberg': '--floyd=1',
+    'ordered': null,
+    'riemersma': null
+};
+
+// Orders accepted by --sort-by
+const SORT_ORDERS = ['manifest', 'featured', 'stars'];
+
+// Source formats accepted in "f", as lowercase file extensions
+const SOURCE_FORMATS = ['png', 'jpg', 'jpeg', 'webp'];
+
+// Keys understood in pack metadata, and in me.json at the contributor level and per wallpaper
+const CONTRIBUTOR_KEYS = ['uname', 'name', 'uri', 'email', 'license', 'avatar', 'links', 'wallpapers'];
+const PACK_KEYS = ['name', 'description', 'maintainer', 'version', 'default'];
+const WALLPAPER_KEYS = ['t', 'i', 'f', 'l', 'tags', 'rating', 'featured', 'stars', 'deprecated', 'variants', 'crops', 'sha256', 'slug', 'default', 'focus', 'version', 'changelog', 'exclude_outputs', 'orientation', 'duration', 'transition'];
+
+// The newest schema spells out the core wallpaper keys; files using either spelling load the same
+const LONG_WALLPAPER_KEYS = { t: 'title', i: 'index', f: 'format', l: 'license' };
+
+// Common spellings which do not squash into an SPDX identifier by themselves
+const LICENSE_ALIASES = {
+    'CC0': 'CC0-1.0',
+    'PUBLIC-DOMAIN': 'CC0-1.0',
+    'GPL-2.0': 'GPL-2.0-only',
+    'GPL-2.0+': 'GPL-2.0-or-later',
+    'GPLV2': 'GPL-2.0-only',
+    'GPLV2+': 'GPL-2.0-or-later',
+    'GPL-3.0': 'GPL-3.0-only',
+    'GPL-3.0+': 'GPL-3.0-or-later',
+    'GPLV3': 'GPL-3.0-only',
+    'GPLV3+': 'GPL-3.0-or-later',
+    'LAL-1.3': 'FAL-1.3'
+};
+
+// Switches take no value; other options take the next argument, and array options may be repeated
+const OPTIONS = {
+    'auto-orient': false,                                    // Apply EXIF orientation and drop the tag before processing
+    'license-map': '',                                       // File of "from=to" lines rewriting legacy license strings
+    'strict': false,                                         // Turn validation warnings into errors
+    'checksums': false,                                      // Write a global DESTDIR.SHA256SUMS next to DESTDIR
+    'checksums-per-artist': false,                           // Write a DESTDIR.SHA256SUMS.<uname> per artist next to DESTDIR
+    'checksums-dir': '',                                     // Directory to write checksum files into, instead of next to DESTDIR
+    'preserve-timestamps': false,                            // Copy source mtimes onto output files
+    'max-rating': 'general',                                 // Highest wallpaper rating to include, see RATINGS
+    'blocklist': '',                                         // File of SHA-256 hashes of images that must never ship
+    'blocklist-action': 'error',                             // What to do with blocklisted images: error or skip
+    'require-tag': [],                                       // Only include wallpapers carrying all of these tags
+    'forbid-tag': [],                                        // Drop wallpapers carrying any of these tags
+    'dedupe-manifest': false,                                // Collapse duplicate manifest selections instead of building them twice
+    'write-clean-manifest': '',                              // Write a sorted, de-duplicated copy of the manifest to this path
+    'include-deprecated': false,                             // Keep deprecated wallpapers, for archival builds
+    'dry-run': false,                                        // Record destination writes in memory and list them instead
+    'write-lock': '',                                        // Write source hashes and outputs of this build to a lock file
+    'only-changed': '',                                      // Only rebuild entries whose sources differ from this previous lock file
+    'prune': false,                                          // With --only-changed, remove outputs of entries no longer in the pack
+    'to-srgb': false,                                        // Convert images with embedded color profiles other than sRGB to sRGB
+    'srgb-profile': '/usr/share/color/icc/colord/sRGB.icc',  // ICC profile used by --to-srgb
+    'prefer-format': '',                                     // Source format to pick when a wallpaper lists several
+    'keep-going': false,                                     // Carry on with the other wallpapers when one fails
+    'report': '',                                            // Write the build report as JSON to this path
+    'exclude-license': [],                                   // Drop wallpapers under these licenses, spelled in any form normalizeLicense knows
+    'update-checksums': false,                               // Record current source hashes into every me.json and exit
+    'retro-depth': '8',                                      // Bits per pixel of retro images: 1, 2, 4, 8 or 24
+    'min-source': '',                                        // Smallest accepted source size as WxH, defaults per variant in MIN_SOURCE
+    'generate-manifest': '',                                 // Write a manifest selecting every contributed wallpaper to this path and exit
+    'scaffold-metadata': '',                                 // Write a starter me.json for the images in this contributor directory and exit
+    'force': false,                                          // Let helper modes overwrite existing files
+    'split-output': '',                                      // Build one tree per artist under this directory instead of DESTDIR
+    'pack-meta': '',                                         // Pack metadata file, defaults to PACKPATH.pack.json when present
+    'display-name': '',                                      // Album display name, overrides pack metadata
+    'description': '',                                       // Pack description, overrides pack metadata
+    'maintainer': '',                                        // Pack maintainer, overrides pack metadata
+    'package-version': '',                                   // Pack version, overrides pack metadata
+    'watch': false,                                          // Rebuild incrementally whenever the manifest or contributors change
+    'gnome-fill': '',                                        // GNOME fill mode: zoom, scaled, centered, spanned, stretched or wallpaper; by orientation when unset
+    'only-input-format': [],                                 // Drop wallpapers whose source is not in one of these formats
+    'license-urls': '',                                      // File mapping SPDX identifiers to URLs, overriding LICENSE_URLS
+    'copy-concurrency': '0',                                 // Most copies into dist running at once, 0 for no limit
+    'manifest-format': 'text',                               // Format of PACKPATH: text or csv
+    'csv-artist-col': 'artist',                              // CSV column holding the contributor, by header name or 1-based number
+    'csv-index-col': 'index',                                // CSV column holding the wallpaper index, by header name or 1-based number
+    'csv-approval-col': '',                                  // Only take CSV rows whose value here is yes, true, approved or 1
+    'csv-no-header': false,                                  // The CSV has no header row, columns are given by number
+    'migrate-metadata': '',                                  // Rewrite me.json in this contributor directory in the newest schema and exit
+    'kde-layout': 'standard',                                // KDE package layout: standard (contents/images) or flat, see KDE_LAYOUTS
+    'all-contributors': false,                               // Select every non-deprecated wallpaper of every contributor instead of reading PACKPATH
+    'print-resolved': false,                                 // Print the resolved selection as manifest lines
+    'validate-desktop': false,                               // Check each metadata.desktop with desktop-file-validate, when installed
+    'thumbnails-only': '',                                   // Only write a thumbnail per wallpaper into this directory
+    'thumbnail-width': '400',                                // Width of thumbnails in pixels
+    'profile': '',                                           // Write a folded-stack timing trace, for flamegraph tools, to this path
+    'retro-format-per-res': '',                              // Per-resolution retro depths as WxH=depth,..., png8 and png24 accepted
+    'dedupe-symlinks': false,                                // Link each KDE package to its image once, under the size it is built at, instead of once per resolution
+    'provenance': '',                                        // Write a JSON record of how this build was produced to this path
+    'reproducible': false,                                   // Leave host name and time out of the provenance record
+    'slideshow': '',                                         // Also write a GNOME slideshow of the album, showing each wallpaper this many seconds
+    'slideshow-transition': '5',                             // Seconds of crossfade between slideshow wallpapers
+    'lint-manifest': '',                                     // Check the syntax and selections of this manifest against contributors, without building, and exit
+    'size-budget': '',                                       // Fail when the output tree is larger than this, like 500M or 2GiB
+    'single-metadata': '',                                   // Read all contributors from this one file, an array of me.json contents, instead of contributors/*/me.json
+    'convert-timeout': '0',                                  // Seconds after which a hung convert is killed and its wallpaper failed, 0 for no limit
+    'squashfs': '',                                          // Also pack the built tree into this squashfs image, with mksquashfs
+    'squashfs-remove-tree': false,                           // Remove the built tree once it is packed by --squashfs
+    'route-tag': [],                                         // Build wallpapers with a tag into a tree of their own as TAG=DIR, the first matching route winning
+    'check-license-compat': false,                           // Warn about licenses in one output tree known not to mix, see LICENSE_CONFLICTS; errors with --strict
+    'quiet': false,                                          // Only print warnings, errors, requested listings and the summary, also -q
+    'verbose': false,                                        // Also print every external command as it runs, also -v
+    'pack-url': '',                                          // Fetch the pack root as a tarball from this http(s) URL, with curl, and read PACKPATH and contributors from it
+    'atomic': false,                                         // Build each tree in a staging copy next to it, and swap it in only when the whole build succeeded
+    'sidecar-attribution': false,                            // Write the title, artist, license and source of each wallpaper to a .txt next to its image
+    'optional-optimization': false,                          // Keep the unquantized PNG when pngquant fails instead of failing the wallpaper
+    'canonical-json': false,                                 // Write JSON outputs with sorted keys and no indentation, for byte-for-byte comparison
+    'sort-by': 'manifest',                                   // Order of wallpapers in the outputs: manifest, featured or stars
+    'imagemagick': 'convert',                                // ImageMagick convert binary to run, or ImageMagick 7's magick
+    'max-resolution': '',                                    // Largest size of normal variant images as WxH, bigger sources are scaled down to fit
+    'allow-empty': false,                                    // Build a pack even when nothing is left to select
+    'manifest-encoding': 'utf-8',                            // Character encoding of the manifest, such as latin-1 or gbk
+    'verify-symlinks': false,                                // After building, report links that do not resolve inside the tree, failing the build with --strict
+    'gresource': '',                                         // Also compile the built tree into this GResource bundle, with glib-compile-resources
+    'gresource-prefix': '/io/aosc/wallpapers',               // Resource path under which --gresource lists the tree
+    'backend-fallback': '',                                  // Convert command to retry a failed conversion with once, such as magick or "gm convert"
+    'retro-dither': '',                                      // Dithering of retro color reduction: none, floyd-steinberg, ordered or riemersma; pngquant's default when unset
+    'plasma-apply-compat': false                             // Make KDE packages loadable by plasma-apply-wallpaperimage: package id as plugin name, and a metadata.json
+};
+// What each programmatic call starts over from
+const OPTION_DEFAULTS = JSON.parse(JSON.stringify(OPTIONS));
+const SHORT_OPTIONS = { q: 'quiet', v: 'verbose' };
+const POSITIONALS = [];
+// Loaded as a module, the command line is not ours to read
+const ARGV = require.main === module ? process.argv.slice(2) : [];
+for (let i = 0; i < ARGV.length; i++) {
+    let arg = ARGV[i];
+    if (arg.match(/^-[a-z]$/) && SHORT_OPTIONS.hasOwnProperty(arg[1])) {
+        arg = `--${SHORT_OPTIONS[arg[1]]}`;
+    };
+    if (arg.indexOf('--') !== 0) {
+        POSITIONALS.push(arg);
+        continue;
+    };
+    let keyname = arg.slice(2);
+    if (!OPTIONS.hasOwnProperty(keyname)) {
+        console.error(`Unknown option: ${arg}`);
+        process.exit(1);
+    };
+    if (typeof OPTIONS[keyname] === 'boolean') {
+        OPTIONS[keyname] = true;
+    } else if (i + 1 >= ARGV.length) {
+        console.error(`Option ${arg} requires a value.`);
+        process.exit(1);
+    } else if (Array.isArray(OPTIONS[keyname])) {
+        OPTIONS[keyname].push(ARGV[++i]);
+    } else {
+        OPTIONS[keyname] = ARGV[++i];
+    };
+};
+
+// Progress goes through console.log, which --quiet silences; say() is for what was asked for anyway
+let say = console.log.bind(console);
+if (OPTIONS.quiet && OPTIONS.verbose) {
+    console.error('Error: --quiet and --verbose cannot be used together.');
+    process.exit(1);
+};
+if (OPTIONS.quiet) {
+    console.log = function () {};
+};
+
+// A programmatic call turns process.exit into a throw, which catches around code that may exit must let through
+const passExit = function (e) {
+    if (e && e.exitStatus !== undefined) {
+        throw e;
+    };
+};
+
+// Helper modes work on the contributors directory alone and exit without building
+const HELPER_MODES = ['update-checksums', 'generate-manifest', 'scaffold-metadata', 'migrate-metadata', 'lint-manifest'];
+const HELPER_MODE = HELPER_MODES.filter(function (x) { return OPTIONS[x] })[0];
+
+if (POSITIONALS.length < 3 && !HELPER_MODE && require.main === module) {
     console.error('Insufficient arguments.');
-    console.error('Usage:    wallcolle-make PACKPATH DESTDIR VARIANT');
+    console.error('Usage:    wallcolle-make [OPTIONS] PACKPATH DESTDIR VARIANT');
+    console.error(`          wallcolle-make --${HELPER_MODES.join('|--')}`);
     process.exit(1);
 };
 
-// --------------------------------------
-// Begin function def
-
-const padright = function (str, len) {
-    if (str.length >= len) {
-        return str;
+// A pack root fetched with --pack-url holds contributors/ and the manifest, possibly inside a single
+// top-level directory. It is extracted to a temporary directory which goes away on exit
+const fetchPack = function (url) {
+    if (!url.match(/^https?:\/\/\S+$/)) {
+        console.error(`Error: Bad --pack-url "${url}", expecting an http(s) URL.`);
+        process.exit(1);
+    };
+    let tmpdir = fs.mkdtempSync(path.join(os.tmpdir(), 'wallcolle-pack-'));
+    process.on('exit', function () {
+        fs.rmSync(tmpdir, { recursive: true, force: true });
+    });
+    console.log(`Fetching pack: ${url}`);
+    let tarball = `${tmpdir}/pack.tar`;
+    [['curl', ['-fsSL', '-o', tarball, url]], ['tar', ['-xf', tarball, '-C', tmpdir]]].forEach(function (step) {
+        let result = spawn(step[0], step[1], { stdio: ['ignore', 'ignore', 'inherit'] });
+        if (result.error || result.status !== 0) {
+            console.error(`Error: Cannot fetch pack from "${url}", ${step[0]} ${result.error ? 'is not installed' : `failed with status ${result.status}`}.`);
+            process.exit(1);
+        };
+    });
+    fs.rmSync(tarball);
+    let toplevel = fs.readdirSync(tmpdir);
+    let root = (toplevel.length === 1 && fs.statSync(`${tmpdir}/${toplevel[0]}`).isDirectory()) ? `${tmpdir}/${toplevel[0]}` : tmpdir;
+    if (!fs.existsSync(`${root}/contributors`)) {
+        console.error(`Error: The pack from "${url}" has no contributors directory.`);
+        process.exit(1);
+    };
+    return root;
+};
+// Set from PACKPATH DESTDIR VARIANT, once by the command line and again by each programmatic call
+let PACK_ROOT;
+let PACKPATH;
+let PACKFILE;
+let PACKNAME;
+let DESTDIR;
+let VARIANT; // Destination variant, can be NORMAL or RETRO
+const setPositionals = function (positionals) {
+    if (OPTIONS['pack-url'] !== '' && (OPTIONS['watch'] || ['update-checksums', 'scaffold-metadata', 'migrate-metadata'].indexOf(HELPER_MODE) !== -1)) {
+        console.error(`Error: --${OPTIONS['watch'] ? 'watch' : HELPER_MODE} works on a local pack, not with --pack-url.`);
+        process.exit(1);
+    };
+    PACK_ROOT = OPTIONS['pack-url'] === '' ? '.' : fetchPack(OPTIONS['pack-url']);
+    PACKPATH = (PACK_ROOT === '.' || !positionals[0]) ? (positionals[0] || '') : path.join(PACK_ROOT, positionals[0]);
+    PACKFILE = PACKPATH.split('/').reverse()[0];
+    // A spreadsheet export is named after the pack, not the other way round
+    PACKNAME = OPTIONS['manifest-format'] === 'csv' ? PACKFILE.replace(/\.csv$/i, '') : PACKFILE;
+    if (PACKNAME === '.' || PACKNAME === '..' || (PACKPATH !== '' && PACKNAME === '')) {
+        console.error(`Error: Cannot name a pack after "${PACKPATH}", expecting a manifest file.`);
+        process.exit(1);
+    };
+    DESTDIR = positionals[1] || '';
+    VARIANT = positionals[2] || '';
+};
+if (require.main === module) {
+    setPositionals(POSITIONALS);
+};
+
+// Serialize a JSON output, pretty-printed or canonical with --canonical-json
+const formatJson = function (value) {
+    if (!OPTIONS['canonical-json']) {
+        return JSON.stringify(value, null, 4) + '\n';
+    };
+    const sortKeys = function (x) {
+        if (Array.isArray(x)) {
+            return x.map(sortKeys);
+        } else if (x !== null && typeof x === 'object') {
+            let sorted = {};
+            Object.keys(x).sort().forEach(function (key) {
+                sorted[key] = sortKeys(x[key]);
+            });
+            return sorted;
+        };
+        return x;
+    };
+    return JSON.stringify(sortKeys(value)) + '\n';
+};
+
+// An attestation of what produced a build, stable across runs unless host and time are asked for
+const writeProvenance = function (provpath, lockEntries) {
+    let record = {
+        tool: { name: 'wallcolle-make', sha256: sha256File(__filename) },
+        node: process.version,
+        // Home directories say more about the builder than about the build
+        args: process.argv.slice(2).map(function (arg) {
+            return arg.split(os.homedir()).join('~');
+        }),
+        manifest: OPTIONS['all-contributors'] ? { all_contributors: true } : { path: PACKPATH, sha256: sha256File(PACKPATH) },
+        variant: VARIANT.toUpperCase(),
+        sources: {}
+    };
+    Object.keys(lockEntries).sort().forEach(function (stdname) {
+        Object.keys(lockEntries[stdname].sources).forEach(function (kind) {
+            record.sources[`${stdname}/${kind}`] = lockEntries[stdname].sources[kind];
+        });
+    });
+    if (!OPTIONS['reproducible']) {
+        record.host = os.hostname();
+        record.timestamp = (new Date()).toISOString();
+    };
+    console.log(`Writing provenance: ${provpath}`);
+    fs.writeFileSync(provpath, formatJson(record));
+};
+
+// Timing spans for --profile, kept as folded stacks: "outer;inner" -> microseconds spent in inner itself
+let profileStack = [];
+let profileSamples = {};
+const profileSpan = function (name, fn) {
+    if (OPTIONS['profile'] === '') {
+        return fn();
+    };
+    profileStack.push({ name: name, children: 0 });
+    let start = process.hrtime.bigint();
+    try {
+        return fn();
+    } finally {
+        let total = Number(process.hrtime.bigint() - start) / 1000;
+        let frame = profileStack.pop();
+        let stack = profileStack.map(function (x) { return x.name }).concat([name]).join(';');
+        profileSamples[stack] = (profileSamples[stack] || 0) + Math.round(total - frame.children);
+        if (profileStack.length > 0) {
+            profileStack[profileStack.length - 1].children += total;
+        };
+    };
+};
+const writeProfile = function () {
+    if (OPTIONS['profile'] === '') {
+        return;
+    };
+    console.log(`Writing profile: ${OPTIONS['profile']}`);
+    fs.writeFileSync(OPTIONS['profile'], Object.keys(profileSamples).map(function (stack) {
+        return `${stack} ${profileSamples[stack]}\n`;
+    }).join(''));
+};
+
+// --------------------------------------
+// Destination filesystem
+// Everything under DESTDIR is written through one of these, so that a build can be recorded instead of performed
+
+const StdFs = {
+    mkdir: function (dirpath) {
+        // A file in the way otherwise fails with a bare EEXIST or ENOTDIR that does not say where
+        let blocker = null;
+        path.resolve(dirpath).split(path.sep).reduce(function (parent, component) {
+            let current = path.join(parent, component);
+            if (blocker === null && fs.existsSync(current) && !fs.statSync(current).isDirectory()) {
+                blocker = current;
+            };
+            return current;
+        }, path.sep);
+        if (blocker === path.resolve(dirpath)) {
+            throw new Error(`${dirpath} exists but is not a directory. Remove it and build again.`);
+        } else if (blocker !== null) {
+            throw new Error(`Cannot create ${dirpath}, ${blocker} is in the way and is not a directory. Remove it and build again.`);
+        };
+        fs.mkdirSync(dirpath, { recursive: true });
+    },
+    copy: function (srcpath, destpath) {
+        fs.copyFileSync(srcpath, destpath);
+    },
+    symlink: function (target, linkpath) {
+        // Rebuilding into an existing tree keeps identical links and swaps the others in one rename
+        let existing = null;
+        try {
+            existing = fs.lstatSync(linkpath);
+        } catch (e) {
+        };
+        if (existing === null) {
+            fs.symlinkSync(target, linkpath);
+        } else if (existing.isDirectory()) {
+            // Whatever is in there was not made by a link, and is not ours to delete
+            throw new Error(`Cannot link "${linkpath}", a directory is in the way.`);
+        } else if (!existing.isSymbolicLink() || fs.readlinkSync(linkpath) !== target) {
+            let temppath = `${linkpath}.tmp-${process.pid}`;
+            fs.symlinkSync(target, temppath);
+            fs.renameSync(temppath, linkpath);
+        };
+    },
+    write: function (filepath, content) {
+        fs.writeFileSync(filepath, content);
+    },
+    run: function (argv) {
+        let converters = [imageMagick('convert')].concat(OPTIONS['backend-fallback'] !== '' ? [OPTIONS['backend-fallback']] : []).map(splitCommand);
+        let timeout = converters.some(function (x) {
+            return x.every(function (word, i) { return argv[i] === word });
+        }) ? parseFloat(OPTIONS['convert-timeout']) * 1000 : 0;
+        if (OPTIONS.verbose) {
+            console.log(`+ ${argv.join(' ')}`);
+        };
+        profileSpan(`run:${argv[0]}`, function () {
+            try {
+                execArgv(argv, { timeout: timeout, killSignal: 'SIGKILL' });
+            } catch (e) {
+                if (e.code === 'ETIMEDOUT') {
+                    throw new Error(`Killed after ${OPTIONS['convert-timeout']} seconds: ${argv.join(' ')}`);
+                };
+                throw e;
+            };
+        });
+    },
+    read: function (filepath) {
+        return fs.readFileSync(filepath);
+    },
+    utimes: function (filepath, atime, mtime) {
+        fs.utimesSync(filepath, atime, mtime);
+    },
+    remove: function (anypath) {
+        fs.rmSync(anypath, { recursive: true, force: true });
+    },
+    // Bytes of the regular files under dirpath
+    treeSize: function (dirpath) {
+        if (!fs.existsSync(dirpath)) {
+            return 0;
+        };
+        return fs.readdirSync(dirpath, { withFileTypes: true }).reduce(function (sum, dirent) {
+            let anypath = `${dirpath}/${dirent.name}`;
+            if (dirent.isDirectory()) {
+                return sum + StdFs.treeSize(anypath);
+            };
+            return sum + (dirent.isFile() ? fs.lstatSync(anypath).size : 0);
+        }, 0);
+    }
+};
+
+// Files are kept by normalized path, since callers join paths with doubled slashes here and there
+const MemFs = function () {
+    this.ops = [];
+    this.files = {};
+};
+MemFs.prototype.mkdir = function (dirpath) {
+    this.ops.push(['mkdir', dirpath]);
+};
+MemFs.prototype.copy = function (srcpath, destpath) {
+    this.ops.push(['copy', srcpath, destpath]);
+    this.files[path.normalize(destpath)] = fs.readFileSync(srcpath);
+};
+MemFs.prototype.symlink = function (target, linkpath) {
+    this.ops.push(['symlink', target, linkpath]);
+};
+MemFs.prototype.write = function (filepath, content) {
+    this.ops.push(['write', filepath]);
+    this.files[path.normalize(filepath)] = Buffer.from(content);
+};
+MemFs.prototype.run = function (argv) {
+    this.ops.push(['run'].concat(argv));
+};
+MemFs.prototype.read = function (filepath) {
+    // Outputs of external commands are not simulated and read as empty
+    return this.files[path.normalize(filepath)] || Buffer.from('');
+};
+MemFs.prototype.utimes = function (filepath, atime, mtime) {
+    this.ops.push(['utimes', filepath, mtime.toISOString()]);
+};
+MemFs.prototype.remove = function (anypath) {
+    this.ops.push(['remove', anypath]);
+    let prefix = path.normalize(`${anypath}/`);
+    Object.keys(this.files).filter(function (filepath) {
+        return filepath === path.normalize(anypath) || filepath.indexOf(prefix) === 0;
+    }).forEach(function (filepath) {
+        delete this.files[filepath];
+    }, this);
+};
+MemFs.prototype.treeSize = function (dirpath) {
+    let prefix = path.normalize(`${dirpath}/`);
+    return Object.keys(this.files).filter(function (filepath) {
+        return filepath.indexOf(prefix) === 0;
+    }).reduce(function (sum, filepath) {
+        return sum + this.files[filepath].length;
+    }.bind(this), 0);
+};
+
+// --------------------------------------
+// Begin function def
+
+const padright = function (str, len) {
+    if (str.length >= len) {
+        return str;
+    };
+    return str + (new Array(len-str.length)).fill(' ').join('');
+};
+
+// Sizes like 500M or 1.5GiB, where K, M, G and T are powers of 1000 and Ki, Mi, Gi and Ti powers of 1024
+const parseSize = function (str) {
+    let mymatch = String(str).trim().match(/^(\d+(?:\.\d+)?)\s*([KMGT]?)(i?)B?$/i);
+    if (!mymatch) {
+        return null;
+    };
+    let power = ' KMGT'.indexOf(mymatch[2].toUpperCase() || ' ');
+    return Math.round(parseFloat(mymatch[1]) * Math.pow(mymatch[3] ? 1024 : 1000, power));
+};
+
+const formatSize = function (bytes) {
+    let power = Math.min(Math.floor(Math.log(Math.max(bytes, 1)) / Math.log(1000)), 4);
+    return power === 0 ? `${bytes} B` : `${(bytes / Math.pow(1000, power)).toFixed(1)} ${' KMGT'[power]}B`;
+};
+
+let buildWarnings = [];
+const warn = function (message) {
+    console.error(`Warning: ${message}`);
+    buildWarnings.push(message);
+};
+
+// Command prefixes given as options, like "magick convert" or "gm convert", as argument lists
+const splitCommand = function (command) {
+    return command.trim().split(/\s+/);
+};
+
+// Runs a program without a shell, so that names from metadata reach it as they are
+const execArgv = function (argv, options) {
+    return execFile(argv[0], argv.slice(1), options);
+};
+
+// Path of a program as a shell would find it on PATH, or null, looked up without running a shell
+const findProgram = function (program) {
+    let candidates = program.indexOf('/') !== -1 ? [program] : (process.env.PATH || '').split(path.delimiter).filter(function (dir) {
+        return dir !== '';
+    }).map(function (dir) {
+        return path.join(dir, program);
+    });
+    return candidates.find(function (candidate) {
+        try {
+            fs.accessSync(candidate, fs.constants.X_OK);
+            return fs.statSync(candidate).isFile();
+        } catch (e) {
+            return false;
+        };
+    }) || null;
+};
+
+const sha256File = function (filepath) {
+    return crypto.createHash('sha256').update(fs.readFileSync(filepath)).digest('hex');
+};
+
+// Reads the pixel size from PNG and JPEG headers, asking ImageMagick about anything else
+const probeImageSize = function (filepath) {
+    let buf = fs.readFileSync(filepath);
+    if (buf.length > 24 && buf.toString('latin1', 1, 4) === 'PNG') {
+        return { width: buf.readUInt32BE(16), height: buf.readUInt32BE(20) };
+    };
+    if (buf[0] === 0xFF && buf[1] === 0xD8) {
+        let pos = 2;
+        while (pos + 9 < buf.length && buf[pos] === 0xFF) {
+            let marker = buf[pos + 1];
+            if (marker >= 0xC0 && marker <= 0xCF && [0xC4, 0xC8, 0xCC].indexOf(marker) === -1) {
+                return { width: buf.readUInt16BE(pos + 7), height: buf.readUInt16BE(pos + 5) };
+            };
+            pos += 2 + buf.readUInt16BE(pos + 2);
+        };
+    };
+    let size = execArgv(splitCommand(imageMagick('identify')).concat(['-format', '%w %h', `${filepath}[0]`])).toString().trim().split(' ');
+    return { width: parseInt(size[0]), height: parseInt(size[1]) };
+};
+
+// Properties which decide whether a source needs converting at all, probed once per source
+let imagePropsCache = {};
+const probeImageProps = function (filepath) {
+    if (!imagePropsCache.hasOwnProperty(filepath)) {
+        let fields = execArgv(splitCommand(imageMagick('identify')).concat(['-format', '%[orientation]\\n%[icc:description]', `${filepath}[0]`]), { stdio: 'pipe' }).toString().split('\n');
+        imagePropsCache[filepath] = { orientation: fields[0].trim(), profile: (fields[1] || '').trim() };
+    };
+    return imagePropsCache[filepath];
+};
+
+// Escapes a string value of a desktop entry, where "=" and ";" are fine but line breaks and backslashes are not;
+// surrounding whitespace is not kept by readers
+const escapeDesktopValue = function (value) {
+    return String(value).trim().replace(/[\x00-\x08\x0b\x0c\x0e-\x1f\x7f]/g, '')
+        .replace(/\\/g, '\\\\').replace(/\n/g, '\\n').replace(/\t/g, '\\t').replace(/\r/g, '\\r');
+};
+
+// Escapes a list value of a desktop entry, where ";" separates and ends the items
+const escapeDesktopList = function (values) {
+    return values.map(function (value) {
+        return escapeDesktopValue(value).replace(/;/g, '\\;');
+    }).join(';') + ';';
+};
+
+// Escapes text and attribute values of the GNOME XML files
+const escapeXml = function (value) {
+    return String(value).replace(/&/g, '&amp;').replace(/</g, '&lt;').replace(/>/g, '&gt;').replace(/"/g, '&quot;').replace(/'/g, '&apos;');
+};
+
+// Window of a fill-resized source to crop for a target size, centered on the focus point where the edges allow
+const getFocusCrop = function (srcSize, scrsize, focus) {
+    let target = scrsize.split('x').map(function (x) { return parseInt(x) });
+    let scale = Math.max(target[0] / srcSize.width, target[1] / srcSize.height);
+    let scaled = [Math.round(srcSize.width * scale), Math.round(srcSize.height * scale)];
+    let offset = [focus.x, focus.y].map(function (f, axis) {
+        let start = Math.round(f * scaled[axis] - target[axis] / 2);
+        return Math.min(Math.max(start, 0), scaled[axis] - target[axis]);
+    });
+    return { width: target[0], height: target[1], x: offset[0], y: offset[1] };
+};
+
+// Names joined into destination paths must stay a single path component
+const checkPathComponent = function (name, what) {
+    if (name === '' || name === '.' || name === '..' || name.match(/[\/\\\0]/)) {
+        console.error(`Error: ${what} "${name}" cannot be used as a file name.`);
+        process.exit(1);
+    };
+    return name;
+};
+
+// Letters of any script are kept, so that a non-Latin name does not collapse into dots
+const getAlbumName = function (name) {
+    name = name.normalize('NFC');
+    let slug = (name.slice(0, 1).toUpperCase() + name.slice(1).toLowerCase()).replace(/[^\p{L}\p{M}\p{N}]/gu, '.');
+    return checkPathComponent(slug.replace(/^\.+|\.+$/g, ''), 'Album name');
+};
+
+const getStdName = function (img) {
+    if (img.slug) {
+        // Explicit override, validated in getUserManifest
+        return checkPathComponent(`${PACKNAME}--${img.uname}--${img.slug}`, 'Entry name');
+    };
+    return checkPathComponent(`${PACKNAME}--${img.uname}--${getTitleSlug(img)}`, 'Entry name');
+};
+
+// Titles without any ASCII letters or digits, like CJK or emoji ones, fall back to the index
+const getTitleSlug = function (img) {
+    let slug = img.t.replace(/[\s\.\-]/g, '_').replace(/_+/g, '_').replace(/[^A-Za-z0-9\.]/g, '');
+    return slug.match(/[A-Za-z0-9]/) ? slug : `w${img.i}`;
+};
+
+// Declared and symlink-resolved paths of every source of a wallpaper, keyed like its hashes;
+// resolved once, so that checks, hashing and copying all read the same file
+const getSourcePaths = function (img) {
+    if (img.paths) {
+        return img.paths;
+    };
+    const resolve = function (declared) {
+        let canonical = declared;
+        try {
+            canonical = fs.realpathSync(declared);
+        } catch (e) {
+        };
+        return { declared: declared, canonical: canonical };
+    };
+    img.paths = { main: resolve(getSourcePath(img.uname, img.i, img.f)) };
+    if (img.variants && img.variants.dark) {
+        img.paths.dark = resolve(`${getContributorDir(img.uname)}/${img.variants.dark}`);
+    };
+    (img.crops || []).forEach(function (crop) {
+        img.paths[`crop-${crop.i}`] = resolve(getSourcePath(img.uname, crop.i, crop.f));
+    });
+    return img.paths;
+};
+
+const getSourceHashes = function (img) {
+    let paths = getSourcePaths(img);
+    let hashes = {};
+    Object.keys(paths).forEach(function (kind) {
+        hashes[kind] = sha256File(paths[kind].canonical);
+    });
+    return hashes;
+};
+
+// Metadata written into the outputs, so that edits to me.json rebuild the entry too
+const getMetadataHash = function (img) {
+    let fields = {};
+    WALLPAPER_KEYS.concat(['uname', 'name', 'uri', 'email', 'displayName']).forEach(function (keyname) {
+        if (img[keyname] !== undefined) {
+            fields[keyname] = img[keyname];
+        };
+    });
+    return crypto.createHash('sha256').update(JSON.stringify(fields)).digest('hex');
+};
+
+const loadLicenseMap = function (mapfile) {
+    let map = {};
+    if (mapfile === '') {
+        return map;
+    };
+    fs.readFileSync(mapfile).toString().split('\n').forEach(function (line, i) {
+        line = line.trim();
+        if (line === '' || line.indexOf('#') === 0) {
+            return;
+        };
+        let eqpos = line.indexOf('=');
+        if (eqpos < 1) {
+            console.error(`Error: Bad license mapping in line ${i} of "${mapfile}".`);
+            console.error(`Statement: "${line}"`);
+            process.exit(1);
+        };
+        map[line.slice(0, eqpos).trim()] = line.slice(eqpos + 1).trim();
+    });
+    return map;
+};
+
+// Album-level information; without a metadata file the pack is named after its manifest
+const loadPackMeta = function () {
+    let metapath = OPTIONS['pack-meta'] || `${PACKPATH}.pack.json`;
+    let meta = {};
+    if (fs.existsSync(metapath)) {
+        try {
+            meta = JSON.parse(fs.readFileSync(metapath).toString());
+        } catch (e) {
+            console.error(`Error: Cannot parse "${metapath}": ${e.message}`);
+            process.exit(1);
+        };
+        checkUnknownKeys(meta, PACK_KEYS, '', metapath);
+    } else if (OPTIONS['pack-meta'] !== '') {
+        console.error(`Error: Pack metadata "${metapath}" does not exist.`);
+        process.exit(1);
+    };
+    return {
+        name: OPTIONS['display-name'] || meta.name || PACKNAME,
+        description: OPTIONS['description'] || meta.description || '',
+        maintainer: OPTIONS['maintainer'] || meta.maintainer || '',
+        version: OPTIONS['package-version'] || meta.version || '',
+        default: meta.default || ''
+    };
+};
+
+// Built-in license URLs with overrides from a JSON, TOML or YAML table of "license = url"
+const loadLicenseUrls = function (urlfile) {
+    let urls = Object.assign({}, LICENSE_URLS);
+    if (urlfile === '') {
+        return urls;
+    };
+    let table;
+    try {
+        table = parseUserMeta(urlfile, fs.readFileSync(urlfile).toString());
+    } catch (e) {
+        passExit(e);
+        console.error(`Error: Cannot parse "${urlfile}": ${e.message}`);
+        process.exit(1);
+    };
+    Object.keys(table).forEach(function (license) {
+        if (!String(table[license]).match(/^https?:\/\/\S+$/)) {
+            console.error(`Error: License URL for "${license}" in "${urlfile}" is not an http(s) URL.`);
+            process.exit(1);
+        };
+        urls[normalizeLicense(license) || license] = table[license];
+    });
+    return urls;
+};
+
+// Returns the SPDX identifier for a license string, or null if it is unknown
+const normalizeLicense = function (license) {
+    if (license.trim().indexOf('LicenseRef-') === 0) {
+        return license.trim();
+    };
+    let squashed = license.trim().toUpperCase().replace(/[\s_]+/g, '-').replace(/-+/g, '-').replace(/-(\d)$/, '-$1.0');
+    if (LICENSE_ALIASES.hasOwnProperty(squashed)) {
+        return LICENSE_ALIASES[squashed];
+    };
+    let found = SPDX_LICENSES.filter(function (x) { return x.toUpperCase() === squashed });
+    return found.length > 0 ? found[0] : null;
+};
+
+const loadBlocklist = function (listfile) {
+    if (listfile === '') {
+        return [];
+    };
+    return fs.readFileSync(listfile).toString().split('\n').map(function (line) {
+        return line.trim().split(/\s+/)[0].toLowerCase();
+    }).filter(function (x) {
+        return x !== '' && x[0] !== '#';
+    });
+};
+
+const editDistance = function (a, b) {
+    let row = Array.from({ length: b.length + 1 }, function (x, j) { return j });
+    for (let i = 1; i <= a.length; i++) {
+        let prev = row[0];
+        row[0] = i;
+        for (let j = 1; j <= b.length; j++) {
+            let saved = row[j];
+            row[j] = Math.min(row[j] + 1, row[j - 1] + 1, prev + (a[i - 1] === b[j - 1] ? 0 : 1));
+            prev = saved;
+        };
+    };
+    return row[b.length];
+};
+
+// Rewrites me.json of a contributor in the newest schema, saying which keys were renamed
+const migrateMetadata = function (userdir) {
+    userdir = userdir.replace(/\/+$/, '');
+    let metapath = `${userdir}/me.json`;
+    if (!fs.existsSync(metapath)) {
+        console.error(`Error: "${metapath}" does not exist, only me.json can be migrated.`);
+        process.exit(1);
+    };
+    let before = fs.readFileSync(metapath).toString();
+    let userdata;
+    try {
+        userdata = JSON.parse(before);
+    } catch (e) {
+        console.error(`Error: Cannot parse "${metapath}": ${e.message}`);
+        process.exit(1);
+    };
+    let renamed = {};
+    (userdata.wallpapers || []).forEach(function (x, i) {
+        Object.keys(LONG_WALLPAPER_KEYS).filter(function (short) {
+            return x.hasOwnProperty(short);
+        }).forEach(function (short) {
+            renamed[short] = (renamed[short] || 0) + 1;
+        });
+        toShortKeys(x, `wallpapers[${i}].`, metapath);
+    });
+    let after = JSON.stringify(toNewestSchema(userdata), null, 4) + '\n';
+    if (after === before) {
+        console.log(`Already in the newest schema: ${metapath}`);
+        return;
+    };
+    Object.keys(renamed).forEach(function (short) {
+        console.log(`Renamed "${short}" to "${LONG_WALLPAPER_KEYS[short]}" in ${renamed[short]} wallpapers.`);
+    });
+    console.log(`Writing: ${metapath}`);
+    fs.writeFileSync(metapath, after);
+};
+
+// Reports keys of obj which are not in knownKeys, suggesting close matches
+const checkUnknownKeys = function (obj, knownKeys, jsonpath, metapath) {
+    Object.keys(obj).filter(function (keyname) {
+        return knownKeys.indexOf(keyname) === -1;
+    }).forEach(function (keyname) {
+        let suggestions = knownKeys.filter(function (x) {
+            return editDistance(keyname, x) <= 2;
+        }).sort(function (a, b) {
+            return editDistance(keyname, a) - editDistance(keyname, b);
+        });
+        let message = `Unknown key "${jsonpath}${keyname}" in "${metapath}".${
+            suggestions.length > 0 ? ` Did you mean "${suggestions[0]}"?` : ''
+        }`;
+        if (OPTIONS.strict) {
+            console.error(`Error: ${message}`);
+            process.exit(1);
+        };
+        warn(message);
+    });
+};
+
+// Names a source may have on disk: either name of jpg, and extensions in any case
+const getSourceCandidates = function (i, f) {
+    return [f, { jpg: 'jpeg', jpeg: 'jpg' }[f]].filter(function (x) { return x }).map(function (ext) {
+        return `${i}.${ext}`;
+    });
+};
+
+// Directory of a contributor as spelled on disk; names are compared in NFC, since directories made
+// on macOS may be NFD while manifests and metadata are NFC
+let contributorDirNames = null;
+const getContributorDir = function (uname) {
+    if (contributorDirNames === null) {
+        contributorDirNames = {};
+        (fs.existsSync(`${PACK_ROOT}/contributors`) ? fs.readdirSync(`${PACK_ROOT}/contributors`) : []).forEach(function (dirname) {
+            contributorDirNames[dirname.normalize('NFC')] = dirname;
+        });
+    };
+    let nfc = String(uname).normalize('NFC');
+    return `${PACK_ROOT}/contributors/${contributorDirNames.hasOwnProperty(nfc) ? contributorDirNames[nfc] : uname}`;
+};
+
+// Sources are found whatever the case of their extension; outputs always use the lowercase format
+const getSourcePath = function (uname, i, f) {
+    f = f.toLowerCase();
+    let userdir = getContributorDir(uname);
+    let candidates = getSourceCandidates(i, f);
+    let found = (fs.existsSync(userdir) ? fs.readdirSync(userdir) : []).filter(function (filename) {
+        return candidates.indexOf(filename.toLowerCase()) !== -1;
+    }).sort(function (a, b) {
+        return candidates.indexOf(a.toLowerCase()) - candidates.indexOf(b.toLowerCase());
+    })[0];
+    return `${userdir}/${found || `${i}.${f}`}`;
+};
+
+// Returns the canonical extension for a declared format, or null when unsupported; jpeg is spelled jpg
+const normalizeFormat = function (format) {
+    let f = String(format).trim().toLowerCase();
+    if (SOURCE_FORMATS.indexOf(f) === -1) {
+        return null;
+    };
+    return f === 'jpeg' ? 'jpg' : f;
+};
+
+// Retro conversion quantizes better from a lossless master, while normal packages ship the smaller export
+const pickSourceFormat = function (formats) {
+    let prefer = normalizeFormat(OPTIONS['prefer-format']);
+    if (formats.indexOf(prefer) !== -1) {
+        return prefer;
+    };
+    let preferred = VARIANT.toUpperCase() === 'RETRO' ? ['png'] : ['jpg', 'jpeg', 'webp'];
+    let found = formats.filter(function (x) { return preferred.indexOf(x) !== -1 });
+    return found.length > 0 ? found[0] : formats[0];
+};
+
+// Contributor metadata may be written in any of these, but only one per contributor
+const METADATA_FILES = ['me.json', 'me.toml', 'me.yaml'];
+
+const getUserMetaPath = function (username) {
+    let found = METADATA_FILES.filter(function (filename) {
+        return fs.existsSync(`${getContributorDir(username)}/${filename}`);
+    });
+    if (found.length !== 1) {
+        console.error(`Error: Expecting exactly one of ${METADATA_FILES.join(', ')} for "${username}", found ${found.length === 0 ? 'none' : found.join(', ')}.`);
+        process.exit(1);
+    };
+    return `${getContributorDir(username)}/${found[0]}`;
+};
+
+// Long keys are read into the short ones used throughout this script
+const toShortKeys = function (x, jsonpath, metapath) {
+    Object.keys(LONG_WALLPAPER_KEYS).forEach(function (short) {
+        let long = LONG_WALLPAPER_KEYS[short];
+        if (!x.hasOwnProperty(long)) {
+            return;
+        };
+        if (x.hasOwnProperty(short)) {
+            console.error(`Error: Both "${jsonpath}${short}" and "${jsonpath}${long}" are set in "${metapath}".`);
+            process.exit(1);
+        };
+        x[short] = x[long];
+        delete x[long];
+    });
+    return x;
+};
+
+// Rebuilds contributor metadata in the newest schema, with keys in a stable order
+const toNewestSchema = function (userdata) {
+    const reorder = function (obj, order) {
+        let out = {};
+        order.concat(Object.keys(obj).sort()).filter(function (keyname) {
+            return obj.hasOwnProperty(keyname) && !out.hasOwnProperty(keyname);
+        }).forEach(function (keyname) {
+            out[keyname] = obj[keyname];
+        });
+        return out;
+    };
+    let wallpaperOrder = WALLPAPER_KEYS.map(function (keyname) {
+        return LONG_WALLPAPER_KEYS[keyname] || keyname;
+    });
+    let migrated = reorder(userdata, CONTRIBUTOR_KEYS);
+    migrated.wallpapers = (userdata.wallpapers || []).map(function (x) {
+        let long = {};
+        Object.keys(x).forEach(function (keyname) {
+            long[LONG_WALLPAPER_KEYS[keyname] || keyname] = x[keyname];
+        });
+        return reorder(long, wallpaperOrder);
+    });
+    return migrated;
+};
+
+// TOML and YAML parsers are only needed by packs which use them
+const requireParser = function (modname, metapath) {
+    try {
+        return require(modname);
+    } catch (e) {
+        if (e.code !== 'MODULE_NOT_FOUND' || e.message.indexOf(`'${modname}'`) === -1) {
+            throw e;
+        };
+        console.error(`Error: Install ${modname} to read "${metapath}", for example with "npm install ${modname}".`);
+        process.exit(1);
+    };
+};
+
+const parseUserMeta = function (metapath, metastr) {
+    if (metapath.match(/\.toml$/)) {
+        return requireParser('@iarna/toml', metapath).parse(metastr);
+    };
+    if (metapath.match(/\.yaml$/)) {
+        return requireParser('js-yaml', metapath).load(metastr);
+    };
+    return JSON.parse(metastr);
+};
+
+const readUserMeta = function (metapath) {
+    let metastr = fs.readFileSync(metapath).toString();
+    try {
+        return parseUserMeta(metapath, metastr);
+    } catch (e) {
+        passExit(e);
+        let posmatch = e.message.match(/position (\d+)/);
+        let where = '';
+        if (posmatch) {
+            let before = metastr.slice(0, parseInt(posmatch[1])).split('\n');
+            where = ` at line ${before.length}, column ${before[before.length - 1].length + 1}`;
+        };
+        console.error(`Error: Cannot parse "${metapath}"${where}: ${e.message}`);
+        process.exit(1);
+    };
+};
+
+// Some packs keep every contributor in one file, as an array of what would be their me.json. Images
+// are still found under contributors/, in the directory named by "uname"
+const loadSingleMetadata = function (metapath) {
+    if (metapath === '') {
+        return null;
+    };
+    let list = readUserMeta(metapath);
+    if (!Array.isArray(list)) {
+        console.error(`Error: "${metapath}" must be an array of contributors.`);
+        process.exit(1);
+    };
+    let byName = {};
+    list.forEach(function (userdata, i) {
+        let uname = userdata && typeof userdata.uname === 'string' ? userdata.uname.normalize('NFC') : null;
+        if (uname === null || !uname.match(/^[\p{L}\p{M}\p{N}_\-]+$/u)) {
+            console.error(`Error: Contributor ${i} in "${metapath}" has no usable "uname", which names its directory under contributors.`);
+            process.exit(1);
+        };
+        if (byName.hasOwnProperty(uname)) {
+            console.error(`Error: Contributor "${uname}" is listed twice in "${metapath}".`);
+            process.exit(1);
+        };
+        if (!fs.existsSync(getContributorDir(uname))) {
+            console.error(`Error: Contributor "${uname}" in "${metapath}" has no directory "${PACK_ROOT}/contributors/${uname}".`);
+            process.exit(1);
+        };
+        byName[uname] = userdata;
+    });
+    return byName;
+};
+
+const getUserManifest = function (username) {
+    let metapath = singleMetadata ? OPTIONS['single-metadata'] : getUserMetaPath(username);
+    let userdata = singleMetadata ? singleMetadata[username] : readUserMeta(metapath);
+    if (!userdata) {
+        console.error(`Error: Contributor "${username}" is not in "${metapath}".`);
+        process.exit(1);
+    };
+    if (!Array.isArray(userdata.wallpapers)) {
+        console.error(`Error: "${metapath}" of "${username}" has no "wallpapers" array.`);
+        process.exit(1);
+    };
+    checkUnknownKeys(userdata, CONTRIBUTOR_KEYS, '', metapath);
+    // "uname" goes into entry names and source paths, like the names in loadSingleMetadata
+    if (typeof userdata.uname !== 'string' || !userdata.uname.normalize('NFC').match(/^[\p{L}\p{M}\p{N}_\-]+$/u)) {
+        console.error(`Error: "${metapath}" of "${username}" has bad uname ${JSON.stringify(userdata.uname)}, expecting letters, digits, "_" and "-".`);
+        process.exit(1);
+    };
+    if (!singleMetadata && userdata.uname.normalize('NFC') !== username) {
+        warn(`"uname" of "${username}" is "${userdata.uname}", which is not its directory name; entry names and source paths use "${userdata.uname}".`);
+    };
+    if (userdata.avatar) {
+        // Copied into "dist" only, avatars never go into DESTDIR
+        if (fs.existsSync(`${getContributorDir(username)}/${userdata.avatar}`)) {
+            userdata.avatarFile = `avatar--${username}.${userdata.avatar.split('.').reverse()[0]}`;
+        } else {
+            warn(`Avatar "${userdata.avatar}" of "${username}" does not exist.`);
+        };
+    };
+    if (userdata.links !== undefined) {
+        if (!Array.isArray(userdata.links)) {
+            console.error(`Error: "links" in "${metapath}" must be an array of { label, url }.`);
+            process.exit(1);
+        };
+        userdata.links.forEach(function (link, i) {
+            if (!link || !link.label || !String(link.url).match(/^https?:\/\/[^\s\/]+\.[^\s]+$/)) {
+                console.error(`Error: Bad link "links[${i}]" in "${metapath}", expecting a label and an http(s) URL.`);
+                process.exit(1);
+            };
+        });
+        // The same profile listed twice is shown once, at its first position
+        userdata.links = userdata.links.filter(function (link, i, arr) {
+            return arr.findIndex(function (y) { return y.url === link.url }) === i;
+        });
+    };
+    userdata.wallpapers.forEach(function (x, i) {
+        toShortKeys(x, `wallpapers[${i}].`, metapath);
+        checkUnknownKeys(x, WALLPAPER_KEYS, `wallpapers[${i}].`, metapath);
+        ['t', 'i', 'f'].forEach(function (keyname) {
+            if (x[keyname] === undefined || x[keyname] === '') {
+                console.error(`Error: Missing key "wallpapers[${i}].${keyname}" in "${metapath}".`);
+                process.exit(1);
+            };
+        });
+        if (!(parseInt(x.i) > 0) || String(parseInt(x.i)) !== String(x.i)) {
+            console.error(`Error: Wallpaper "${x.t}" in "${metapath}" has index ${JSON.stringify(x.i)}, expecting a positive integer.`);
+            process.exit(1);
+        };
+        if (x.slug === undefined && getTitleSlug(x) === `w${x.i}` && !String(x.t).match(/^w\d+$/)) {
+            warn(`Wallpaper "${x.t}" of "${username}" gets the entry name suffix "w${x.i}", consider setting a "slug".`);
+        };
+        if (x.slug !== undefined && !String(x.slug).match(/^[A-Za-z0-9][A-Za-z0-9\.\-]*$/)) {
+            console.error(`Error: Wallpaper "${x.t}" in "${metapath}" has bad slug ${JSON.stringify(x.slug)}, expecting ASCII letters, digits, dots and dashes.`);
+            process.exit(1);
+        };
+        let firstIndex = userdata.wallpapers.findIndex(function (y) { return parseInt(y.i) === parseInt(x.i) });
+        if (firstIndex !== i) {
+            console.error(`Error: Duplicate index ${x.i} in "${metapath}", used by "${userdata.wallpapers[firstIndex].t}" and "${x.t}".`);
+            process.exit(1);
+        };
+    });
+    userdata.wallpapers.map(function (x, i) {
+        ['uname','name','uri','email'].map(function (keyname) {
+            userdata.wallpapers[i][keyname] = userdata[keyname];
+        });
+        // Fix up case, stray whitespace and jpg/jpeg mixups before the format is used in paths
+        let formats = [].concat(x.f).map(function (f) {
+            let normalized = normalizeFormat(f);
+            if (normalized === null) {
+                console.error(`Error: Wallpaper "${x.t}" of "${username}" has unsupported format ${JSON.stringify(f)}, expecting one of ${SOURCE_FORMATS.join(', ')}.`);
+                process.exit(1);
+            };
+            return normalized;
+        });
+        userdata.wallpapers[i].f = Array.isArray(x.f) ? formats : formats[0];
+        // Pick one source when several encodings are provided
+        if (Array.isArray(x.f)) {
+            userdata.wallpapers[i].formats = x.f;
+            userdata.wallpapers[i].f = pickSourceFormat(x.f);
+        };
+        // Inherit the artist-level default license
+        if (!x.l) {
+            if (!userdata.license) {
+                console.error(`Error: Wallpaper "${x.t}" of "${username}" has no license, and no default license is set.`);
+                process.exit(1);
+            };
+            userdata.wallpapers[i].l = userdata.license;
+        } else if (userdata.license && OPTIONS.verbose && normalizeLicense(String(x.l)) !== normalizeLicense(String(userdata.license))) {
+            console.log(`Wallpaper "${x.t}" of "${username}" overrides default license "${userdata.license}" with "${x.l}".`);
+        };
+        if (licenseMap.hasOwnProperty(userdata.wallpapers[i].l)) {
+            userdata.wallpapers[i].l = licenseMap[userdata.wallpapers[i].l];
+        };
+        if (x.orientation !== undefined && ['landscape', 'portrait'].indexOf(x.orientation) === -1) {
+            console.error(`Error: Wallpaper "${x.t}" of "${username}" has orientation "${x.orientation}", expecting landscape or portrait.`);
+            process.exit(1);
+        };
+        if (x.tags !== undefined && !(Array.isArray(x.tags) && x.tags.every(function (y) { return typeof y === 'string' }))) {
+            console.error(`Error: Wallpaper "${x.t}" of "${username}" has tags that are not an array of strings.`);
+            process.exit(1);
+        };
+        (x.tags || []).forEach(function (tag) {
+            if (tag.match(/[\x00-\x1f\x7f]/)) {
+                console.error(`Error: Wallpaper "${x.t}" of "${username}" has tag ${JSON.stringify(tag)} with control characters.`);
+                process.exit(1);
+            };
+            if (tag.indexOf(';') !== -1) {
+                warn(`Wallpaper "${x.t}" of "${username}" has tag "${tag}" with a semicolon, which keyword lists escape.`);
+            };
+        });
+        if (x.exclude_outputs !== undefined && !(Array.isArray(x.exclude_outputs) && x.exclude_outputs.every(function (y) {
+            return OUTPUT_TARGETS.indexOf(y) !== -1;
+        }))) {
+            console.error(`Error: Wallpaper "${x.t}" of "${username}" has bad exclude_outputs ${JSON.stringify(x.exclude_outputs)}, expecting a list of ${OUTPUT_TARGETS.join(', ')}.`);
+            process.exit(1);
+        };
+        ['duration', 'transition'].forEach(function (keyname) {
+            if (x[keyname] !== undefined && !(typeof x[keyname] === 'number' && x[keyname] > 0)) {
+                console.error(`Error: Wallpaper "${x.t}" of "${username}" has ${keyname} ${JSON.stringify(x[keyname])}, expecting a positive number of seconds.`);
+                process.exit(1);
+            };
+        });
+        if (x.version !== undefined && !(Number.isInteger(x.version) && x.version > 0)) {
+            console.error(`Error: Wallpaper "${x.t}" of "${username}" has version ${JSON.stringify(x.version)}, expecting a positive integer.`);
+            process.exit(1);
+        };
+        if (x.changelog !== undefined && !(Array.isArray(x.changelog) && x.changelog.every(function (y) { return typeof y === 'string' }))) {
+            console.error(`Error: Wallpaper "${x.t}" of "${username}" has a changelog that is not an array of strings.`);
+            process.exit(1);
+        };
+        if (x.focus !== undefined && !['x', 'y'].every(function (axis) {
+            return typeof x.focus[axis] === 'number' && x.focus[axis] >= 0 && x.focus[axis] <= 1;
+        })) {
+            console.error(`Error: Wallpaper "${x.t}" of "${username}" has bad focus ${JSON.stringify(x.focus)}, expecting { "x": 0.0-1.0, "y": 0.0-1.0 }.`);
+            process.exit(1);
+        };
+        (x.crops || []).forEach(function (crop) {
+            let normalized = normalizeFormat(crop.f);
+            if (normalized === null) {
+                console.error(`Error: Crop ${crop.i} of wallpaper "${x.t}" of "${username}" has unsupported format ${JSON.stringify(crop.f)}.`);
+                process.exit(1);
+            };
+            crop.f = normalized;
+            if (!String(crop.aspect).match(/^\d+[:\-]\d+$/)) {
+                console.error(`Error: Crop ${crop.i} of wallpaper "${x.t}" of "${username}" has bad aspect "${crop.aspect}", expecting W:H.`);
+                process.exit(1);
+            };
+        });
+        userdata.wallpapers[i].rating = x.rating || 'general';
+        if (RATINGS.indexOf(userdata.wallpapers[i].rating) === -1) {
+            console.error(`Error: Wallpaper "${x.t}" of "${username}" has unknown rating "${x.rating}".`);
+            process.exit(1);
+        };
+        if (x.featured !== undefined && typeof x.featured !== 'boolean') {
+            console.error(`Error: Wallpaper "${x.t}" of "${username}" has featured ${JSON.stringify(x.featured)}, expecting true or false.`);
+            process.exit(1);
+        };
+        if (x.stars !== undefined && !(Number.isInteger(x.stars) && x.stars >= 1 && x.stars <= 5)) {
+            console.error(`Error: Wallpaper "${x.t}" of "${username}" has stars ${JSON.stringify(x.stars)}, expecting an integer from 1 to 5.`);
+            process.exit(1);
+        };
+        let spdx = normalizeLicense(userdata.wallpapers[i].l);
+        if (spdx === null) {
+            let message = `Wallpaper "${x.t}" of "${username}" has unknown license "${userdata.wallpapers[i].l}".`;
+            if (OPTIONS.strict) {
+                console.error(`Error: ${message}`);
+                process.exit(1);
+            };
+            warn(message);
+        } else {
+            userdata.wallpapers[i].l = spdx;
+        };
+    });
+    return userdata;
+};
+
+const buildDatabase = function () {
+    let db = {};
+    let users = singleMetadata ? Object.keys(singleMetadata) : fs.readdirSync(`${PACK_ROOT}/contributors`).filter(function (x) { return x[0] !== '.' }).map(function (x) {
+        return x.normalize('NFC');
+    });
+    if (OPTIONS['all-contributors'] && !singleMetadata) {
+        // Stray directories should not stop an archive build
+        users = users.filter(function (username) {
+            if (METADATA_FILES.some(function (filename) { return fs.existsSync(`${getContributorDir(username)}/${filename}`) })) {
+                return true;
+            };
+            warn(`Contributor directory "${username}" has no metadata, skipping.`);
+            return false;
+        });
+    };
+    users.forEach(function (username) {
+        db[username] = profileSpan('getUserManifest', function () {
+            return getUserManifest(username);
+        });
+    });
+    console.log('Building database...');
+    // console.log(db);
+    return db;
+};
+
+// Entries of each artist, artists in order of first appearance; nothing for no entries
+const groupByArtist = function (entries) {
+    let groups = [];
+    entries.forEach(function (entry) {
+        let group = groups.find(function (x) { return x.uname === entry.uname });
+        if (!group) {
+            group = { uname: entry.uname, entries: [] };
+            groups.push(group);
+        };
+        if (group.entries.indexOf(entry) === -1) {
+            group.entries.push(entry);
+        };
+    });
+    return groups;
+};
+
+// Wallpapers carrying a routed tag go to the tree of the first such route, the others to DESTDIR
+const routeByTag = function (entries) {
+    let trees = [{ label: 'default', entries: [], destdir: DESTDIR, albumname: getAlbumName(PACKNAME) }].concat(tagRoutes.map(function (route) {
+        return { label: route.tag, entries: [], destdir: route.destdir, albumname: `${getAlbumName(PACKNAME)}.${getAlbumName(route.tag)}` };
+    }));
+    entries.forEach(function (entry) {
+        let tags = (entry.tags || []).map(function (x) { return x.toLowerCase() });
+        let routeIndex = tagRoutes.findIndex(function (route) {
+            return tags.indexOf(route.tag.toLowerCase()) !== -1;
+        });
+        trees[routeIndex + 1].entries.push(entry);
+    });
+    return trees.filter(function (tree) { return tree.entries.length > 0 });
+};
+
+const renderPackManifest = function (obj) {
+    const renderLines = function (entries) {
+        return entries.map(function (entry) {
+            return [
+                padright(entry.displayName.slice(0, 32) + (entry.version > 1 ? ` (v${entry.version})` : ''), 33), '   ',
+                padright(entry.name, 17), '   ',
+                entry.l
+            ].join('');
+        }).join('\n');
+    };
+    const renderLicenses = function (entries) {
+        return entries.map(function (entry) {
+            return entry.l;
+        }).filter(function (license, i, arr) {
+            return arr.indexOf(license) === i;
+        }).sort().map(function (license) {
+            return [padright(license, 37), '   ', licenseUrls[license] || ''].join('').trim();
+        }).join('\n');
+    };
+    const renderContributors = function (entries) {
+        return groupByArtist(entries).map(function (group) {
+            let uname = group.uname;
+            return [[
+                padright(db[uname].name, 17), '   ',
+                padright(db[uname].uri || '', 37), '   ',
+                db[uname].avatarFile || ''
+            ].join('').trim()].concat((db[uname].links || []).map(function (link) {
+                return `${padright('', 20)}${link.label}: ${link.url}`;
+            })).join('\n');
+        }).join('\n');
+    };
+    return `
+PackName   :   ${PACKNAME}
+Album      :   ${packMeta.name}
+Version    :   ${packMeta.version}
+Maintainer :   ${packMeta.maintainer}
+Default    :   ${obj.default ? `${obj.default.t} (${obj.default.uname})` : ''}
+DateTime   :   ${obj.date.slice(0, 19).replace('T', ' ')}
+Quantity   :   ${obj.entries.length}
+
+Comments   :
+${packMeta.description}${packMeta.description ? '\n' : ''}${obj.comments.join('\n').replace(/# /g, '#     ')}
+
+Title                               Contributor         License
+===============================================================================
+` + renderLines(obj.entries) + `
+
+License                                 URL
+===============================================================================
+` + renderLicenses(obj.entries) + `
+
+Contributor         Homepage                                Avatar
+===============================================================================
+` + renderContributors(obj.entries) + '\n';
+};
+
+// Match an ARTIST:INDEX declaration, tolerating stray whitespace around the
+// segments and a trailing comma; returns null when the line is not one
+const parseDeclaration = function (line) {
+    let mymatch = line.trim().normalize('NFC').match(/^([\p{L}\p{M}\p{N}_\-]+)\s*:\s*(\d+)\s*(,?)$/u);
+    if (!mymatch) {
+        return null;
+    };
+    return {
+        uname: mymatch[1],
+        index: mymatch[2],
+        token: `${mymatch[1]}:${mymatch[2]}`,
+        comma: mymatch[3] === ','
+    };
+};
+
+const parsePackDef = function (deffile) {
+    // Leading blank lines are kept, so that line numbers stay those of the file
+    let rawdata = deffile.replace(/\s+$/, '').split('\n');
+    let catalog = [];
+    let manifestObj = {
+        title: PACKNAME,
+        date: (new Date()).toISOString(),
+        comments: [],
+        entries: [],
+        filtered: [],
+        failed: [] // Report outcomes of entries dropped before building
+    };
+    let duplicateCount = 0;
+    let declarationCount = 0;
+    rawdata.forEach(function (line, i) {
+        line = line.trim();
+        if (line.length > 2 && line.indexOf('# ') !== 0) {
+            // Not comment
+            declarationCount += 1;
+            let decl = parseDeclaration(line);
+            let mymatch = decl && [decl.token, decl.uname, decl.index];
+            if (decl && decl.comma) {
+                warn(`Line ${i + 1}: Trailing comma after "${decl.token}", ignoring it. List one ARTIST:INDEX per line without separators.`);
+            };
+            if (mymatch && OPTIONS['dedupe-manifest'] && catalog.some(function (x) {
+                return x.uname === mymatch[1] && parseInt(x.index) === parseInt(mymatch[2]);
+            })) {
+                duplicateCount += 1;
+            } else if (mymatch) {
+                // Yes, good declaration line.
+                catalog.push({
+                    uname: mymatch[1],
+                    index: mymatch[2]
+                });
+                if (!db[mymatch[1]] || !db[mymatch[1]].wallpapers[mymatch[2]]) {
+                    console.error(`Error: Line ${i + 1} selects ${db[mymatch[1]] ? `wallpaper ${mymatch[2]} of "${mymatch[1]}", which does not exist` : `unknown contributor "${mymatch[1]}"`}.`);
+                    console.error(`Statement: "${decl.token}"`);
+                    process.exit(1);
+                };
+                let entry = db[mymatch[1]].wallpapers[mymatch[2]];
+                entry.line = entry.line || i + 1;
+                if (entry.deprecated && !OPTIONS['include-deprecated']) {
+                    warn(`Line ${i + 1} selects deprecated wallpaper "${entry.t}" of "${entry.uname}", skipping.`);
+                    console.error(`Statement: "${decl.token}"`);
+                    manifestObj.filtered.push(entry);
+                } else if (RATINGS.indexOf(entry.rating) > RATINGS.indexOf(OPTIONS['max-rating'])) {
+                    console.log(`Skipping "${entry.t}" of "${entry.uname}": rated ${entry.rating}.`);
+                    manifestObj.filtered.push(entry);
+                } else {
+                    manifestObj.entries.push(entry);
+                };
+            } else {
+                console.error(`Error: Bad declaration in line ${i + 1}, expecting ARTIST:INDEX. Please fix before proceeding.`);
+                console.error(`Statement: "${line}"`);
+                process.exit(1);
+            };
+        } else if (line.indexOf('# ') === 0) {
+            // Comment
+            manifestObj.comments.push(line);
+        };
+    });
+    if (duplicateCount > 0) {
+        console.log(`Collapsed ${duplicateCount} duplicate manifest lines.`);
+    };
+    applyFormatFilters(manifestObj);
+    applyLicenseFilters(manifestObj);
+    applyTagFilters(manifestObj);
+    checkSourceFiles(manifestObj);
+    checkNameCollisions(manifestObj);
+    preflightCheck(manifestObj);
+    // An empty album would still be packaged, so say where the selection went
+    if (manifestObj.entries.length === 0 && !OPTIONS['allow-empty']) {
+        console.error(`Error: Nothing to build for "${PACKNAME}": read ${declarationCount} declaration lines, parsed ${catalog.length + duplicateCount} (${duplicateCount} duplicates), ${manifestObj.filtered.length} filtered out, ${manifestObj.failed.length} dropped.`);
+        console.error('Pass --allow-empty if an empty pack is intended.');
+        process.exit(1);
+    };
+    resolveDefaultWallpaper(manifestObj);
+    assignDisplayNames(manifestObj);
+    sortEntries(manifestObj);
+    manifestObj.entries.map(function (entry) {
+        return entry.l;
+    }).filter(function (license, i, arr) {
+        return arr.indexOf(license) === i && !licenseUrls[license];
+    }).forEach(function (license) {
+        warn(`License "${license}" has no known URL, add one with --license-urls.`);
+    });
+    return {
+        catalog: catalog,
+        manifestObj: manifestObj,
+        manifestStr: renderPackManifest(manifestObj)
+    };
+};
+
+const applyLicenseFilters = function (manifestObj) {
+    let excluded = OPTIONS['exclude-license'].map(function (x) {
+        return normalizeLicense(x) || x;
+    });
+    let removedBy = {};
+    manifestObj.entries = manifestObj.entries.filter(function (entry) {
+        if (excluded.indexOf(entry.l) === -1) {
+            return true;
+        };
+        removedBy[entry.l] = (removedBy[entry.l] || 0) + 1;
+        manifestObj.filtered.push(entry);
+        return false;
+    });
+    Object.keys(removedBy).forEach(function (license) {
+        console.log(`Excluded ${removedBy[license]} wallpapers under ${license}.`);
+    });
+};
+
+// Splits CSV text into rows of fields, honouring double quotes; each row keeps the line it starts on as "line"
+const parseCsv = function (text) {
+    let rows = [[]];
+    let field = '';
+    let quoted = false;
+    let lineNo = 1;
+    rows[0].line = lineNo;
+    for (let i = 0; i < text.length; i++) {
+        let c = text[i];
+        if (quoted) {
+            if (c === '"' && text[i + 1] === '"') {
+                field += '"';
+                i += 1;
+            } else if (c === '"') {
+                quoted = false;
+            } else {
+                if (c === '\n' || (c === '\r' && text[i + 1] !== '\n')) {
+                    lineNo += 1;
+                };
+                field += c;
+            };
+        } else if (c === '"') {
+            quoted = true;
+        } else if (c === ',') {
+            rows[rows.length - 1].push(field);
+            field = '';
+        } else if (c === '\n' || c === '\r') {
+            if (c === '\r' && text[i + 1] === '\n') {
+                i += 1;
+            };
+            rows[rows.length - 1].push(field);
+            field = '';
+            lineNo += 1;
+            rows.push([]);
+            rows[rows.length - 1].line = lineNo;
+        } else {
+            field += c;
+        };
+    };
+    rows[rows.length - 1].push(field);
+    return rows.filter(function (row) {
+        return row.some(function (x) { return x.trim() !== '' });
+    });
+};
+
+// Turns a curation spreadsheet export into manifest lines, ignoring columns we do not know about. Each
+// declaration sits on the line its row starts on, so that errors point at the spreadsheet row
+const csvToPackDef = function (text) {
+    let rows = parseCsv(text);
+    if (rows.length === 0) {
+        console.error(`Error: "${PACKPATH}" is an empty CSV manifest.`);
+        process.exit(1);
+    };
+    let header = OPTIONS['csv-no-header'] ? [] : rows.shift().map(function (x) { return x.trim().toLowerCase() });
+    const getColumn = function (optname) {
+        let col = OPTIONS[optname];
+        let pos = col.match(/^\d+$/) ? parseInt(col) - 1 : header.indexOf(col.trim().toLowerCase());
+        if (pos < 0) {
+            console.error(`Error: CSV column "${col}" of --${optname} not found in "${PACKPATH}".`);
+            process.exit(1);
+        };
+        return pos;
+    };
+    let artistCol = getColumn('csv-artist-col');
+    let indexCol = getColumn('csv-index-col');
+    let approvalCol = OPTIONS['csv-approval-col'] === '' ? -1 : getColumn('csv-approval-col');
+    let unapproved = 0;
+    let lines = new Array(rows.length > 0 ? rows[rows.length - 1].line : 0).fill('');
+    rows.filter(function (row) {
+        if (approvalCol === -1 || ['yes', 'true', 'approved', '1'].indexOf((row[approvalCol] || '').trim().toLowerCase()) !== -1) {
+            return true;
+        };
+        unapproved += 1;
+        return false;
+    }).forEach(function (row) {
+        lines[row.line - 1] = `${(row[artistCol] || '').trim()}:${(row[indexCol] || '').trim()}`;
+    });
+    if (unapproved > 0) {
+        console.log(`Skipped ${unapproved} CSV rows not approved in "${OPTIONS['csv-approval-col']}".`);
+    };
+    return lines.join('\n');
+};
+
+// --manifest-encoding as a TextDecoder label, where ISO 8859-1 is spelled latin1
+const getManifestEncoding = function () {
+    return OPTIONS['manifest-encoding'].toLowerCase().replace(/^latin-(\d)$/, 'latin$1');
+};
+
+// Manifest bytes as text; anything not valid in --manifest-encoding is an error rather than replaced
+const decodeManifest = function (buf, manifestpath) {
+    try {
+        return new TextDecoder(getManifestEncoding(), { fatal: true }).decode(buf);
+    } catch (e) {
+        console.error(`Error: "${manifestpath}" is not valid ${OPTIONS['manifest-encoding']}, set its encoding with --manifest-encoding.`);
+        process.exit(1);
+    };
+};
+
+// The manifest text to build, whichever way the selection is given
+const readPackDef = function () {
+    if (OPTIONS['all-contributors']) {
+        let lines = [`# All contributors`];
+        Object.keys(db).sort().forEach(function (username) {
+            db[username].wallpapers.map(function (x, i) {
+                return { x: x, pos: i };
+            }).filter(function (y) {
+                return !y.x.deprecated || OPTIONS['include-deprecated'];
+            }).sort(function (a, b) {
+                return a.x.i - b.x.i;
+            }).forEach(function (y) {
+                lines.push(`${username}:${y.pos}`);
+            });
+        });
+        return lines.join('\n');
+    };
+    let stdin;
+    try {
+        stdin = fs.readFileSync(PACKPATH);
+    } catch (e) {
+        console.error(`Error: Cannot make "${PACKNAME}".`);
+        process.exit(1);
+    };
+    let deffile = decodeManifest(stdin, PACKPATH);
+    return OPTIONS['manifest-format'] === 'csv' ? csvToPackDef(deffile) : deffile;
+};
+
+const applyFormatFilters = function (manifestObj) {
+    if (OPTIONS['only-input-format'].length === 0) {
+        return;
+    };
+    // Spelled like normalized "f" values, where jpg and jpeg are the same format
+    let allowed = [].concat.apply([], OPTIONS['only-input-format'].map(function (x) {
+        x = x.trim().toLowerCase();
+        return (x === 'jpg' || x === 'jpeg') ? ['jpg', 'jpeg'] : [x];
+    }));
+    let removedBy = {};
+    manifestObj.entries = manifestObj.entries.filter(function (entry) {
+        let usable = (entry.formats || [entry.f]).filter(function (f) { return allowed.indexOf(f) !== -1 });
+        if (usable.length > 0) {
+            if (usable.indexOf(entry.f) === -1) {
+                entry.f = pickSourceFormat(usable);
+            };
+            return true;
+        };
+        removedBy[entry.f] = (removedBy[entry.f] || 0) + 1;
+        manifestObj.filtered.push(entry);
+        return false;
+    });
+    Object.keys(removedBy).forEach(function (format) {
+        console.log(`Skipped ${removedBy[format]} wallpapers with ${format} sources.`);
+    });
+};
+
+// Featured or higher-starred wallpapers go first, ties keep their manifest order
+const sortEntries = function (manifestObj) {
+    const keyOf = {
+        featured: function (entry) { return entry.featured ? 1 : 0 },
+        stars: function (entry) { return entry.stars || 0 }
+    }[OPTIONS['sort-by']];
+    if (keyOf) {
+        manifestObj.entries.sort(function (a, b) {
+            return keyOf(b) - keyOf(a);
+        });
+    };
+};
+
+// Titles shared by several wallpapers of the pack get the artist name, then the index, so that names shown to users stay apart
+const assignDisplayNames = function (manifestObj) {
+    const disambiguate = function (entries, getName) {
+        let counts = {};
+        entries.forEach(function (entry) {
+            counts[getName(entry)] = (counts[getName(entry)] || 0) + 1;
+        });
+        return entries.filter(function (entry) { return counts[getName(entry)] > 1 });
+    };
+    manifestObj.entries.forEach(function (entry) {
+        entry.displayName = entry.t;
+    });
+    disambiguate(manifestObj.entries, function (entry) { return entry.t }).forEach(function (entry) {
+        entry.displayName = `${entry.t} (${entry.name})`;
+    });
+    disambiguate(manifestObj.entries, function (entry) { return entry.displayName }).forEach(function (entry) {
+        entry.displayName = `${entry.t} (${entry.name}, ${entry.i})`;
+    });
+};
+
+// At most one wallpaper of the resolved pack may be the default, claimed either in me.json or in pack metadata
+const resolveDefaultWallpaper = function (manifestObj) {
+    let claimants = manifestObj.entries.filter(function (entry) {
+        return entry.default === true;
+    }).map(function (entry) {
+        return { entry: entry, source: `"default" in me.json of "${entry.uname}"` };
+    });
+    if (packMeta.default !== '') {
+        let mymatch = String(packMeta.default).normalize('NFC').match(/^([\p{L}\p{M}\p{N}_\-]+):(\d+)$/u);
+        let entry = mymatch && db[mymatch[1]] && db[mymatch[1]].wallpapers[mymatch[2]];
+        if (!entry || manifestObj.entries.indexOf(entry) === -1) {
+            console.error(`Error: Default wallpaper "${packMeta.default}" in pack metadata is not part of the pack.`);
+            process.exit(1);
+        };
+        if (claimants.every(function (x) { return x.entry !== entry })) {
+            claimants.push({ entry: entry, source: `"default" in pack metadata` });
+        };
+    };
+    if (claimants.length > 1) {
+        console.error(`Error: ${claimants.length} wallpapers claim to be the default, expecting one:`);
+        claimants.forEach(function (x) {
+            console.error(`    "${x.entry.t}" of "${x.entry.uname}", by ${x.source}`);
+        });
+        process.exit(1);
+    };
+    manifestObj.default = claimants.length === 1 ? claimants[0].entry : null;
+};
+
+const applyTagFilters = function (manifestObj) {
+    if (OPTIONS['require-tag'].length === 0 && OPTIONS['forbid-tag'].length === 0) {
+        return;
+    };
+    let removedBy = {};
+    let selectedCount = manifestObj.entries.length;
+    manifestObj.entries = manifestObj.entries.filter(function (entry) {
+        let tags = (entry.tags || []).map(function (x) { return x.toLowerCase() });
+        let reasons = OPTIONS['require-tag'].filter(function (tag) {
+            return tags.indexOf(tag.toLowerCase()) === -1;
+        }).map(function (tag) {
+            return `--require-tag ${tag}`;
+        }).concat(OPTIONS['forbid-tag'].filter(function (tag) {
+            return tags.indexOf(tag.toLowerCase()) !== -1;
+        }).map(function (tag) {
+            return `--forbid-tag ${tag}`;
+        }));
+        reasons.forEach(function (reason) {
+            removedBy[reason] = (removedBy[reason] || 0) + 1;
+        });
+        if (reasons.length > 0) {
+            console.log(`Skipping "${entry.t}" of "${entry.uname}": ${reasons.join(', ')}.`);
+            manifestObj.filtered.push(entry);
+        };
+        return reasons.length === 0;
+    });
+    if (selectedCount > 0 && manifestObj.entries.length === 0 && !OPTIONS['allow-empty']) {
+        console.error(`Error: Tag filters eliminated all ${selectedCount} selected wallpapers.`);
+        Object.keys(removedBy).forEach(function (reason) {
+            console.error(`    ${reason} removed ${removedBy[reason]}`);
+        });
+        process.exit(1);
+    };
+};
+
+// Every missing file of the selection is reported at once, before anything is written
+const checkSourceFiles = function (manifestObj) {
+    let missing = [];
+    // Symlinks pointing nowhere exist in the listing, say where they point
+    const dangling = function (declared) {
+        try {
+            return ` (${declared} links to missing ${fs.readlinkSync(declared)})`;
+        } catch (e) {
+            return '';
+        };
+    };
+    manifestObj.entries.forEach(function (entry) {
+        let where = `wallpaper "${entry.t}" of "${entry.uname}" (manifest line ${entry.line})`;
+        let paths = getSourcePaths(entry);
+        if (!fs.existsSync(paths.main.canonical)) {
+            missing.push(`${getSourceCandidates(entry.i, entry.f).join(' or ')} for ${where}${dangling(paths.main.declared)}`);
+        };
+        if (paths.dark && !fs.existsSync(paths.dark.canonical)) {
+            missing.push(`dark variant ${entry.variants.dark} for ${where}${dangling(paths.dark.declared)}`);
+        };
+        (entry.crops || []).forEach(function (crop) {
+            let cropPath = paths[`crop-${crop.i}`];
+            if (!fs.existsSync(cropPath.canonical)) {
+                missing.push(`crop ${getSourceCandidates(crop.i, crop.f).join(' or ')} for ${where}${dangling(cropPath.declared)}`);
+            };
+        });
+    });
+    if (missing.length > 0) {
+        console.error(`Error: ${missing.length} source files are missing, extensions matched in any case:`);
+        missing.forEach(function (x) {
+            console.error(`    ${x}`);
+        });
+        process.exit(1);
+    };
+};
+
+// Entry names must be unique, whether computed from titles or given as slugs, or a later wallpaper
+// would overwrite the outputs of an earlier one. Only the selection matters: unselected wallpapers
+// write nothing. With --keep-going, the later wallpapers of each pair are dropped
+const checkNameCollisions = function (manifestObj) {
+    let seen = {};
+    let collisions = 0;
+    manifestObj.entries = manifestObj.entries.filter(function (entry) {
+        let stdname = getStdName(entry);
+        let first = seen[stdname];
+        if (!first || first === entry) {
+            seen[stdname] = entry;
+            return true;
+        };
+        let message = `Wallpapers "${first.t}" and "${entry.t}" of "${entry.uname}" share the entry name "${stdname}".`;
+        collisions += 1;
+        if (!OPTIONS['keep-going']) {
+            console.error(`Error: ${message}`);
+            return false;
+        };
+        warn(`${message} Keeping the first.`);
+        manifestObj.failed.push({ entry_name: stdname, ok: false, status: 'failed', version: entry.version || 1, changelog: entry.changelog || [], excluded_outputs: entry.exclude_outputs || [], bytes: 0, outputs: [], error: message });
+        return false;
+    });
+    if (collisions > 0 && !OPTIONS['keep-going']) {
+        console.error('Set a distinct "slug" on one of each pair.');
+        process.exit(1);
+    };
+};
+
+// Checks run against the selected sources before anything is written
+const preflightCheck = function (manifestObj) {
+    let minSource = (OPTIONS['min-source'] || MIN_SOURCE[VARIANT.toUpperCase()] || '0x0').split('x').map(function (x) {
+        return parseInt(x);
+    });
+    manifestObj.entries.filter(function () {
+        return minSource[0] > 0 || minSource[1] > 0;
+    }).forEach(function (entry) {
+        let size = probeImageSize(getSourcePaths(entry).main.canonical);
+        entry.width = size.width;
+        entry.height = size.height;
+        // Portrait sources are held against the transposed requirement
+        let required = size.height > size.width ? [minSource[1], minSource[0]] : minSource;
+        if (size.width < required[0] || size.height < required[1]) {
+            let message = `Wallpaper ${entry.i} ("${entry.t}") of "${entry.uname}" is ${size.width}x${size.height}, smaller than the required ${required.join('x')}.`;
+            if (!OPTIONS['keep-going']) {
+                console.error(`Error: ${message}`);
+                process.exit(1);
+            };
+            warn(message);
+        };
+    });
+    manifestObj.entries.filter(function (entry) {
+        return !entry.orientation;
+    }).forEach(function (entry) {
+        let size = entry.width ? entry : probeImageSize(getSourcePaths(entry).main.canonical);
+        entry.orientation = size.height > size.width ? 'portrait' : 'landscape';
+    });
+    manifestObj.entries.forEach(function (entry) {
+        // Hashes are recorded for the declared file only, not for a pick among several formats
+        if (entry.sha256 && !entry.formats) {
+            let srcpath = getSourcePaths(entry).main.declared;
+            let digest = sha256File(getSourcePaths(entry).main.canonical);
+            if (digest !== entry.sha256.toLowerCase()) {
+                console.error(`Error: Checksum mismatch for "${srcpath}" ("${entry.t}" of "${entry.uname}").`);
+                console.error(`Expected: ${entry.sha256}`);
+                console.error(`Actual:   ${digest}`);
+                process.exit(1);
+            };
+        };
+    });
+    if (blocklist.length > 0) {
+        manifestObj.entries = manifestObj.entries.filter(function (entry) {
+            // Every source counts: the main image, its dark variant and its crops
+            let paths = getSourcePaths(entry);
+            let blocked = Object.keys(paths).map(function (kind) { return paths[kind] }).filter(function (x) {
+                return blocklist.indexOf(sha256File(x.canonical)) !== -1;
+            }).map(function (x) {
+                return x.declared;
+            });
+            if (blocked.length === 0) {
+                return true;
+            };
+            if (OPTIONS['blocklist-action'] !== 'skip') {
+                console.error(`Error: Wallpaper "${entry.t}" of "${entry.uname}" is blocklisted (${blocked.join(', ')}).`);
+                process.exit(1);
+            };
+            warn(`Skipping blocklisted wallpaper "${entry.t}" of "${entry.uname}" (${blocked.join(', ')}).`);
+            manifestObj.filtered.push(entry);
+            return false;
+        });
+    };
+    checkImageTools(manifestObj);
+};
+
+// What the installed ImageMagick can do, probed once per run
+let imageMagickCaps = null;
+// Command line of an ImageMagick tool; --imagemagick names convert, and its siblings sit next to it
+const imageMagick = function (tool) {
+    let im = OPTIONS['imagemagick'];
+    if (path.basename(im) === 'magick') {
+        return `${im} ${tool}`;
+    };
+    if (tool === 'convert') {
+        return im;
+    };
+    return im.indexOf('/') === -1 ? tool : path.join(path.dirname(im), tool);
+};
+
+// Runs a conversion, retried once with --backend-fallback when the primary convert fails on it
+const runConvert = function (args) {
+    try {
+        destfs.run(splitCommand(imageMagick('convert')).concat(args));
+    } catch (e) {
+        if (OPTIONS['backend-fallback'] === '') {
+            throw e;
+        };
+        warn(`${imageMagick('convert')} failed, retrying with ${OPTIONS['backend-fallback']}: ${e.message.split('\n')[0]}`);
+        destfs.run(splitCommand(OPTIONS['backend-fallback']).concat(args));
+    };
+};
+
+const getImageMagickCaps = function () {
+    if (imageMagickCaps) {
+        return imageMagickCaps;
+    };
+    imageMagickCaps = { version: null, formats: {} };
+    try {
+        imageMagickCaps.version = execArgv(splitCommand(imageMagick('convert')).concat(['-version']), { stdio: 'pipe' }).toString().split('\n')[0].replace(/^Version:\s*/, '').replace(/\s+https?:\S+\s*$/, '').trim();
+        execArgv(splitCommand(imageMagick('convert')).concat(['-list', 'format']), { stdio: 'pipe' }).toString().split('\n').forEach(function (line) {
+            // Like "     JPEG* JPEG      rw-   Joint Photographic Experts Group JFIF format"
+            let mymatch = line.match(/^\s*([A-Z0-9\-]+)\*?\s+\S+\s+([r\-])([w\-])[\+\-]/);
+            if (mymatch) {
+                imageMagickCaps.formats[mymatch[1]] = { read: mymatch[2] === 'r', write: mymatch[3] === 'w' };
+            };
+        });
+    } catch (e) {
+    };
+    return imageMagickCaps;
+};
+
+// Missing tools or delegates otherwise fail the build one wallpaper at a time, after outputs were written
+const checkImageTools = function (manifestObj) {
+    let retro = VARIANT.toUpperCase() === 'RETRO';
+    let thumbnails = OPTIONS['thumbnails-only'] !== '';
+    if (OPTIONS['dry-run'] || manifestObj.entries.length === 0 || !(retro || thumbnails || getPrepareArgs().length > 0)) {
+        return;
+    };
+    let caps = getImageMagickCaps();
+    let im = imageMagick('convert');
+    if (caps.version !== null && caps.version.match(/GraphicsMagick/)) {
+        // Its convert takes the same arguments, but writes broken PNGs that fail only later in pngquant
+        console.error(`Error: ${im} is GraphicsMagick ("${caps.version}"), not ImageMagick, and cannot be used for this build.`);
+        console.error('Install ImageMagick, or point --imagemagick at its convert or magick binary.');
+        process.exit(1);
+    };
+    if (caps.version === null || !caps.version.match(/ImageMagick/)) {
+        console.error(`Error: ${caps.version === null ? `${im} is not installed` : `${im} is not ImageMagick ("${caps.version}")`}, but this build converts images.`);
+        console.error('Point --imagemagick at ImageMagick\'s convert or magick binary.');
+        process.exit(1);
+    };
+    let version = (caps.version.match(/ImageMagick (\d+)\.(\d+)\.(\d+)/) || []).slice(1).map(Number);
+    let minimum = MIN_IMAGEMAGICK.split('.').map(Number);
+    const older = function (a, b) {
+        return a.length > 0 && (a[0] < b[0] || (a[0] === b[0] && (a[1] < b[1] || (a[1] === b[1] && a[2] < b[2]))));
+    };
+    if (older(version, minimum)) {
+        console.error(`Error: ${im} is ${caps.version}, older than the supported minimum ImageMagick ${MIN_IMAGEMAGICK}.`);
+        process.exit(1);
+    };
+    let needs = {};
+    manifestObj.entries.forEach(function (entry) {
+        let exts = [entry.f].concat((entry.variants && entry.variants.dark) ? [entry.variants.dark.split('.').reverse()[0].toLowerCase()] : []);
+        exts.forEach(function (ext) {
+            needs[ext === 'jpg' ? 'JPEG' : ext.toUpperCase()] = { read: true };
+        });
+    });
+    let outputFormat = thumbnails ? 'JPEG' : (retro ? 'PNG' : null);
+    if (outputFormat) {
+        needs[outputFormat] = Object.assign({}, needs[outputFormat], { write: true });
+    };
+    let gaps = [];
+    Object.keys(needs).sort().forEach(function (format) {
+        ['read', 'write'].filter(function (mode) {
+            return needs[format][mode] && !(caps.formats[format] && caps.formats[format][mode]);
+        }).forEach(function (mode) {
+            gaps.push(`cannot ${mode} ${format}`);
+        });
+    });
+    let depths = Object.keys(retroDepths).map(function (x) { return retroDepths[x] }).concat([OPTIONS['retro-depth']]);
+    if (retro && !thumbnails && RETRO_DITHERS[OPTIONS['retro-dither']] !== null && depths.some(function (x) { return x !== '24' })) {
+        try {
+            exec('command -v pngquant', { stdio: 'pipe' });
+        } catch (e) {
+            gaps.push('pngquant is not installed');
+        };
+    };
+    if (gaps.length > 0) {
+        console.error(`Error: ${caps.version} is missing what this build needs: ${gaps.join(', ')}.`);
+        process.exit(1);
+    };
+};
+
+const writeCleanManifest = function (cleanpath, catalog, comments) {
+    let lines = [];
+    catalog.forEach(function (x) {
+        let line = `${x.uname}:${parseInt(x.index)}`;
+        if (lines.indexOf(line) === -1) {
+            lines.push(line);
+        };
+    });
+    lines.sort(function (a, b) {
+        let pa = a.split(':');
+        let pb = b.split(':');
+        return pa[0] === pb[0] ? parseInt(pa[1]) - parseInt(pb[1]) : (pa[0] < pb[0] ? -1 : 1);
+    });
+    console.log(`Writing clean manifest: ${cleanpath}`);
+    fs.writeFileSync(cleanpath, comments.concat(lines).join('\n') + '\n');
+};
+
+const updateChecksums = function () {
+    fs.readdirSync(`${PACK_ROOT}/contributors`).filter(function (x) { return x[0] !== '.' }).forEach(function (username) {
+        let metapath = getUserMetaPath(username);
+        if (!metapath.match(/\.json$/)) {
+            warn(`Not updating checksums in "${metapath}", only me.json can be rewritten.`);
+            return;
+        };
+        let userdata = JSON.parse(fs.readFileSync(metapath).toString());
+        let changed = 0;
+        userdata.wallpapers.forEach(function (x, i) {
+            let short = toShortKeys(Object.assign({}, x), `wallpapers[${i}].`, metapath);
+            if (Array.isArray(short.f)) {
+                warn(`Wallpaper "${short.t}" of "${username}" has several formats, not recording a checksum.`);
+                return;
+            };
+            // Spelled as the build reads it, so that " PNG " finds the same file
+            let format = normalizeFormat(String(short.f).normalize('NFC'));
+            if (format === null) {
+                warn(`Wallpaper "${short.t}" of "${username}" has unsupported format ${JSON.stringify(short.f)}, not recording a checksum.`);
+                return;
+            };
+            let srcpath = getSourcePath(username, short.i, format);
+            if (!fs.existsSync(srcpath)) {
+                warn(`Source "${srcpath}" of wallpaper "${short.t}" of "${username}" does not exist, not recording a checksum.`);
+                return;
+            };
+            let digest = sha256File(srcpath);
+            if (x.sha256 !== digest) {
+                x.sha256 = digest;
+                changed += 1;
+            };
+        });
+        if (changed > 0) {
+            console.log(`Updating ${changed} checksums in "${metapath}"`);
+            fs.writeFileSync(metapath, JSON.stringify(userdata, null, 4) + '\n');
+        };
+    });
+};
+
+const generateManifest = function (manifestpath) {
+    let lines = ['# Generated from all contributors'];
+    fs.readdirSync(`${PACK_ROOT}/contributors`).filter(function (x) { return x[0] !== '.' }).sort().forEach(function (username) {
+        getUserManifest(username).wallpapers.forEach(function (x, i) {
+            lines.push(`${username}:${i}`);
+        });
+    });
+    console.log(`Writing manifest with ${lines.length - 1} wallpapers: ${manifestpath}`);
+    fs.writeFileSync(manifestpath, lines.join('\n') + '\n');
+};
+
+// Reports every problem at once instead of stopping at the first, and reads only metadata, not images
+const lintManifest = function (manifestpath) {
+    let deffile = decodeManifest(fs.readFileSync(manifestpath), manifestpath);
+    if (OPTIONS['manifest-format'] === 'csv') {
+        deffile = csvToPackDef(deffile);
+    };
+    let problems = [];
+    let wallpaperLists = {};
+    const getWallpapers = function (username) {
+        if (wallpaperLists.hasOwnProperty(username)) {
+            return wallpaperLists[username];
+        };
+        if (singleMetadata) {
+            wallpaperLists[username] = singleMetadata[username] ? singleMetadata[username].wallpapers || [] : null;
+            return wallpaperLists[username];
+        };
+        let wallpapers = null;
+        let found = fs.existsSync(getContributorDir(username)) ? METADATA_FILES.filter(function (filename) {
+            return fs.existsSync(`${getContributorDir(username)}/${filename}`);
+        }) : [];
+        if (found.length === 1) {
+            let metapath = `${getContributorDir(username)}/${found[0]}`;
+            try {
+                wallpapers = parseUserMeta(metapath, fs.readFileSync(metapath).toString()).wallpapers || [];
+            } catch (e) {
+                problems.push(`"${metapath}" cannot be read: ${e.message}`);
+            };
+        } else if (found.length > 1) {
+            problems.push(`Contributor "${username}" has more than one of ${METADATA_FILES.join(', ')}.`);
+        };
+        wallpaperLists[username] = wallpapers;
+        return wallpapers;
+    };
+    let seen = {};
+    let selected = 0;
+    deffile.split('\n').forEach(function (line, i) {
+        line = line.trim();
+        if (line === '' || line.indexOf('# ') === 0 || line === '#') {
+            return;
+        };
+        let decl = parseDeclaration(line);
+        if (!decl) {
+            problems.push(`Line ${i + 1}: Bad declaration "${line}", expecting ARTIST:INDEX.`);
+            return;
+        };
+        if (decl.comma) {
+            problems.push(`Line ${i + 1}: Trailing comma after "${decl.token}", list one ARTIST:INDEX per line without separators.`);
+        };
+        let mymatch = [decl.token, decl.uname, decl.index];
+        let key = `${mymatch[1]}:${parseInt(mymatch[2])}`;
+        if (seen.hasOwnProperty(key) && OPTIONS['dedupe-manifest']) {
+            return;
+        } else if (seen.hasOwnProperty(key)) {
+            problems.push(`Line ${i + 1}: Selects ${key} again, first selected on line ${seen[key]}.`);
+            return;
+        };
+        seen[key] = i + 1;
+        let wallpapers = getWallpapers(mymatch[1]);
+        let entry = wallpapers === null ? null : wallpapers[parseInt(mymatch[2])];
+        if (singleMetadata ? !singleMetadata.hasOwnProperty(mymatch[1]) : !fs.existsSync(getContributorDir(mymatch[1]))) {
+            problems.push(`Line ${i + 1}: Unknown contributor "${mymatch[1]}".`);
+        } else if (wallpapers !== null && entry === undefined) {
+            problems.push(`Line ${i + 1}: Wallpaper ${mymatch[2]} of "${mymatch[1]}" is out of range, it has ${wallpapers.length} wallpapers.`);
+        } else if (entry && entry.deprecated && !OPTIONS['include-deprecated']) {
+            // Builds skip these, so the pack would silently come out short
+            problems.push(`Line ${i + 1}: Wallpaper ${mymatch[2]} of "${mymatch[1]}" is deprecated, drop it or build with --include-deprecated.`);
+        } else {
+            selected += 1;
+        };
+    });
+    problems.forEach(function (problem) {
+        console.error(problem);
+    });
+    if (problems.length > 0) {
+        console.error(`Found ${problems.length} problems in "${manifestpath}".`);
+        process.exit(1);
+    };
+    console.log(`Manifest "${manifestpath}" is fine, selecting ${selected} wallpapers.`);
+};
+
+// Returns whatever EXIF fields ImageMagick can find, or an empty object
+const readExif = function (filepath) {
+    try {
+        let fields = execArgv(splitCommand(imageMagick('identify')).concat(['-format', '%[EXIF:Artist]\\n%[EXIF:ImageDescription]\\n%[EXIF:Copyright]', `${filepath}[0]`]), {
+            stdio: ['ignore', 'pipe', 'ignore']
+        }).toString().split('\n');
+        return { artist: fields[0].trim(), title: fields[1].trim(), copyright: fields[2].trim() };
+    } catch (e) {
+        return {};
+    };
+};
+
+const scaffoldMetadata = function (userdir) {
+    userdir = userdir.replace(/\/+$/, '');
+    let metapath = `${userdir}/me.json`;
+    METADATA_FILES.filter(function (filename) {
+        return fs.existsSync(`${userdir}/${filename}`) && !(filename === 'me.json' && OPTIONS.force);
+    }).forEach(function (filename) {
+        console.error(`Error: "${userdir}/${filename}" already exists${filename === 'me.json' ? ', pass --force to overwrite it' : ''}.`);
+        process.exit(1);
+    });
+    let todo = [];
+    let images = fs.readdirSync(userdir).filter(function (filename) {
+        return filename.match(/^[^\.].*\.(png|jpe?g|webp)$/i);
+    }).sort();
+    let nextIndex = images.reduce(function (max, filename) {
+        return Math.max(max, parseInt(filename) || 0);
+    }, 0) + 1;
+    let userdata = {
+        uname: userdir.split('/').reverse()[0],
+        name: '',
+        uri: '',
+        email: '',
+        wallpapers: images.map(function (filename) {
+            let ext = filename.split('.').reverse()[0];
+            let index = filename.match(/^\d+\.[^\.]+$/) ? parseInt(filename) : nextIndex++;
+            if (String(index) !== filename.split('.')[0]) {
+                todo.push(`Rename "${filename}" to "${index}.${ext.toLowerCase()}"`);
+            };
+            let exif = readExif(`${userdir}/${filename}`);
+            return {
+                t: exif.title || '',
+                i: index,
+                f: ext.toLowerCase(),
+                l: (exif.copyright && normalizeLicense(exif.copyright)) || '',
+                exifArtist: exif.artist || ''
+            };
+        }).sort(function (a, b) {
+            return a.i - b.i;
+        })
+    };
+    userdata.wallpapers.forEach(function (x) {
+        if (x.exifArtist && userdata.name === '') {
+            userdata.name = x.exifArtist;
+        };
+        delete x.exifArtist;
+        ['t', 'l'].filter(function (keyname) { return x[keyname] === '' }).forEach(function (keyname) {
+            todo.push(`Fill in "${LONG_WALLPAPER_KEYS[keyname]}" of wallpaper ${x.i}`);
+        });
+    });
+    ['name', 'uri', 'email'].filter(function (keyname) { return userdata[keyname] === '' }).forEach(function (keyname) {
+        todo.push(`Fill in "${keyname}"`);
+    });
+    console.log(`Writing ${userdata.wallpapers.length} wallpapers: ${metapath}`);
+    fs.writeFileSync(metapath, JSON.stringify(toNewestSchema(userdata), null, 4) + '\n');
+    if (todo.length > 0) {
+        console.log(`TODO:\n${todo.map(function (x) { return `    ${x}` }).join('\n')}`);
+    };
+};
+
+// ImageMagick arguments applied to sources before anything else
+// Without a source path, returns every argument that may apply
+const getPrepareArgs = function (srcpath) {
+    let args = [];
+    if (OPTIONS['auto-orient'] && (!srcpath || ['', 'Undefined', 'TopLeft'].indexOf(probeImageProps(srcpath).orientation) === -1)) {
+        // Images already upright are copied as they are instead of being re-encoded
+        args.push('-auto-orient');
+    };
+    let profile = srcpath && OPTIONS['to-srgb'] ? probeImageProps(srcpath).profile : null;
+    if (OPTIONS['to-srgb'] && (profile === null || (profile !== '' && !profile.match(/\bsRGB\b/i)))) {
+        // Untagged and sRGB images are copied as they are; only other profiles need converting
+        args.push('-profile', OPTIONS['srgb-profile']);
+    };
+    if (OPTIONS['max-resolution'] !== '' && VARIANT.toUpperCase() !== 'RETRO') {
+        let size = srcpath ? probeImageSize(srcpath) : null;
+        let cap = getResolutionCap(size);
+        if (!size || size.width > cap[0] || size.height > cap[1]) {
+            args.push('-resize', `${cap.join('x')}>`);
+        };
+    };
+    return args;
+};
+
+// --max-resolution is a ceiling, not a size; portraits are held against the transposed cap
+const getResolutionCap = function (size) {
+    let cap = OPTIONS['max-resolution'].split('x').map(Number);
+    if (size && size.height > size.width) {
+        cap.reverse();
+    };
+    return cap;
+};
+
+// Size of an image once --max-resolution has scaled it down to fit, keeping its shape
+const getCappedSize = function (size) {
+    if (OPTIONS['max-resolution'] === '' || VARIANT.toUpperCase() === 'RETRO') {
+        return size;
+    };
+    let cap = getResolutionCap(size);
+    let scale = Math.min(cap[0] / size.width, cap[1] / size.height, 1);
+    return { width: Math.max(1, Math.round(size.width * scale)), height: Math.max(1, Math.round(size.height * scale)) };
+};
+
+// Previews for galleries, without any of the package tree
+const genThumbnails = function (manifestObj, outdir) {
+    destfs.mkdir(outdir);
+    manifestObj.entries.forEach(function (img) {
+        let srcimgpath = getSourcePaths(img).main.canonical;
+        let thumbpath = `${outdir}/${getStdName(img)}.jpg`;
+        console.log(`Generating thumbnail: ${thumbpath}`);
+        runConvert([srcimgpath].concat(getPrepareArgs(srcimgpath), ['-thumbnail', `${OPTIONS['thumbnail-width']}x`, '-quality', '85', thumbpath]));
+    });
+    console.log(`Wrote ${manifestObj.entries.length} thumbnails into "${outdir}".`);
+    if (OPTIONS['dry-run']) {
+        say(`Dry run, recorded ${destfs.ops.length} operations:`);
+        destfs.ops.forEach(function (op) {
+            say(`    ${op.join(' ')}`);
+        });
+    };
+};
+
+const checkLicenseCompat = function (entries, destdir) {
+    let licenses = entries.map(function (x) { return x.l }).filter(function (x, i, arr) {
+        return arr.indexOf(x) === i;
+    }).sort();
+    let conflicts = [];
+    LICENSE_CONFLICTS.forEach(function (rule) {
+        licenses.filter(function (x) { return rule.a.test(x) }).forEach(function (a) {
+            licenses.filter(function (x) { return x !== a && rule.b.test(x) }).forEach(function (b) {
+                conflicts.push(`${a} and ${b} are mixed in "${destdir}": ${rule.reason}.`);
+            });
+        });
+    });
+    conflicts.forEach(function (message) {
+        if (OPTIONS.strict) {
+            console.error(`Error: ${message}`);
+        } else {
+            warn(message);
+        };
+    });
+    if (conflicts.length > 0 && OPTIONS.strict) {
+        process.exit(1);
+    };
+};
+
+// With --atomic, destdir is a staging copy and finaldir where it will end up, which is what the lock records
+const finisherScript = function (manifestObj, destdir, albumname, lockEntries, finaldir) {
+    finaldir = finaldir || destdir;
+    /* Tree structure:
+    usr
+        share
+            background-properties
+                image-title.myname.xml
+            backgrounds
+                image-title.myname
+                    image-title.myname.png
+                xfce
+                    image-title.myname-1-1.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
+                    image-title.myname-16-10.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
+                    image-title.myname-16-9.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
+                    image-title.myname-21-9.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
+                    image-title.myname-3-2.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
+                    image-title.myname-4-3.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
+                    image-title.myname-5-4.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
+            gnome-background-properties
+                image-title.myname.xml -> /usr/share/background-properties/image-title.myname.xml
+            mate-background-properties
+                image-title.myname.xml -> /usr/share/background-properties/image-title.myname.xml
+            wallpapers
+                image-title.myname
+                    contents
+                        images
+                            1024x768.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
+                            1152x768.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
+                            1280x1024.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
+                            1280x800.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
+                            1280x854.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
+                            1280x960.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
+                            1366x768.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
+                            1440x900.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
+                            1440x960.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
+                            1600x1200.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
+                            1600x900.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
+                            1680x1050.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
+                            1920x1080.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
+                            1920x1200.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
+                            2048x1536.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
+                            2048x2048.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
+                            2160x1440.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
+                            2520x1080.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
+                            3360x1440.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
+                            2560x2048.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
+                            2560x1600.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
+                            2880x1800.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
+                            3000x2000.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
+                            3840x2160.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
+                            4096x4096.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
+                            4500x3000.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
+                            5120x4096.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
+                            800x600.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
+                        screenshot.png
+                    metadata.desktop
+    */
+    if (OPTIONS['check-license-compat']) {
+        checkLicenseCompat(manifestObj.entries, finaldir);
+    };
+    // Failures outside of any one wallpaper stop the build, saying what was being written where
+    const albumStep = function (what, fn) {
+        try {
+            fn();
+        } catch (e) {
+            passExit(e);
+            console.error(`Error: Cannot ${what} in "${destdir}": ${e.message}`);
+            process.exit(1);
+        };
+    };
+    albumStep('create the directory layout', function () {
+        [
+            '/usr',
+            '/usr/share',
+            '/usr/share/backgrounds',
+            '/usr/share/backgrounds/xfce',
+            '/usr/share/background-properties',
+            '/usr/share/gnome-background-properties',
+            '/usr/share/mate-background-properties',
+            '/usr/share/wallpapers'
+        ].forEach(function (dirpath) {
+            destfs.mkdir(`${destdir}${dirpath}`);
+        });
+    });
+    console.log(`------------------------------\n\n`);
+    // console.log(manifestObj);
+    let albumItemsArr = [];
+    let slideshowFrames = []; // Images shown by the slideshow, in album order
+    let outputFiles = []; // Regular files written, with paths relative to destdir
+    let previousLock = OPTIONS['only-changed'] === '' ? null : JSON.parse(fs.readFileSync(OPTIONS['only-changed']).toString());
+    const pruneEntry = function (stdname) {
+        destfs.remove(`${destdir}/usr/share/backgrounds/${stdname}`);
+        destfs.remove(`${destdir}/usr/share/wallpapers/${stdname}`);
+        if (fs.existsSync(`${destdir}/usr/share/backgrounds/xfce`)) {
+            fs.readdirSync(`${destdir}/usr/share/backgrounds/xfce`).filter(function (filename) {
+                return filename.indexOf(`${stdname}-`) === 0 && filename.slice(stdname.length).match(/^-\d+-\d+\.[^\.]+$/);
+            }).forEach(function (filename) {
+                destfs.remove(`${destdir}/usr/share/backgrounds/xfce/${filename}`);
+            });
+        };
+    };
+    const genAlbumItem = function (img, abspathImg, abspathImgDark) {
+        return `<wallpaper delete="false">
+            <name>${escapeXml(img.displayName)}</name>
+            <filename>${escapeXml(abspathImg)}</filename>${
+                abspathImgDark ? `\n            <filename-dark>${escapeXml(abspathImgDark)}</filename-dark>` : ''
+            }
+            <artist>${escapeXml(img.name)}</artist>${
+                // Comments cannot hold "--"
+                licenseUrls[img.l] ? `\n            <!-- License: ${img.l.replace(/-{2,}/g, '-')} <${licenseUrls[img.l].replace(/-{2,}/g, '-')}> -->` : ''
+            }
+            <options>${OPTIONS['gnome-fill'] || (img.orientation === 'portrait' ? 'centered' : 'zoom')}</options>
+        </wallpaper>`;
+    };
+    const addAlbumItem = function (img, abspathImg, abspathImgDark) {
+        albumItemsArr.push(genAlbumItem(img, abspathImg, abspathImgDark));
+        slideshowFrames.push({ img: img, abspath: abspathImg });
+    };
+    const writeDesktopEntry = function (img, mockpathMds) {
+        // KPackage identifies a package by its plugin name, which has to be the name of its directory
+        let pluginName = OPTIONS['plasma-apply-compat'] ? getStdName(img) : img.displayName;
+        destfs.write(mockpathMds, [
+            '[Desktop Entry]',
+            'Type=Service',
+            `Name=${escapeDesktopValue(img.displayName)}`,
+            '',
+            `X-KDE-PluginInfo-Name=${escapeDesktopValue(pluginName)}`,
+            `X-KDE-PluginInfo-Author=${escapeDesktopValue(img.name)}`,
+            `X-KDE-PluginInfo-License=${escapeDesktopValue(img.l)}`
+        ].concat(img.email ? [`X-KDE-PluginInfo-Email=${escapeDesktopValue(img.email)}`] : [])
+            .concat((img.tags || []).length > 0 ? [`X-KDE-Keywords=${escapeDesktopList(img.tags)}`] : [])
+            .concat(img.featured ? ['X-Featured=true'] : [])
+            .concat(img.stars ? [`X-Rating=${img.stars}`] : []).join('\n') + '\n');
+        if (OPTIONS['validate-desktop'] && !OPTIONS['dry-run']) {
+            try {
+                execArgv(['desktop-file-validate', mockpathMds], { stdio: 'pipe' });
+            } catch (e) {
+                let output = String(e.stdout || '').trim() || e.message;
+                warn(`${mockpathMds} did not validate: ${output}`);
+            };
+        };
+        if (OPTIONS['plasma-apply-compat']) {
+            // Plasma 6 reads packages from metadata.json only
+            let mockpathJson = mockpathMds.replace(/metadata\.desktop$/, 'metadata.json');
+            console.log(`Writing metadata.json: ${mockpathJson}`);
+            destfs.write(mockpathJson, formatJson({
+                KPackageStructure: 'Wallpaper/Images',
+                KPlugin: {
+                    Authors: [Object.assign({ Name: img.name }, img.email ? { Email: img.email } : {})],
+                    Id: pluginName,
+                    License: img.l,
+                    Name: img.displayName
+                }
+            }));
+        };
+    };
+    const writeAlbumXml = function (albumItemsArr) {
+        // Write config
+        let mockpathXml = `${destdir}/usr/share/background-properties/${albumname}.xml`;
+        console.log(`Writing XML: ${mockpathXml}`);
+        destfs.write(mockpathXml, `<?xml version='1.0' encoding='UTF-8'?>
+        <!DOCTYPE wallpapers SYSTEM "gnome-wp-list.dtd">
+        <wallpapers>
+            ${ albumItemsArr.join('\n\n') }
+        </wallpapers>`);
+        // Relative links resolve in the staged tree as well as once installed. Links left behind by
+        // albums since renamed or dropped point at nothing, and are cleared out
+        ['gnome', 'mate'].forEach(function (desktop) {
+            let linkdir = `${destdir}/usr/share/${desktop}-background-properties`;
+            destfs.symlink(`../background-properties/${albumname}.xml`, `${linkdir}/${albumname}.xml`);
+            (fs.existsSync(linkdir) ? fs.readdirSync(linkdir) : []).filter(function (filename) {
+                let linkpath = `${linkdir}/${filename}`;
+                if (filename === `${albumname}.xml` || !fs.lstatSync(linkpath).isSymbolicLink()) {
+                    return false;
+                };
+                let target = fs.readlinkSync(linkpath);
+                return target.indexOf('background-properties/') !== -1 && !fs.existsSync(target[0] === '/' ? `${destdir}${target}` : `${linkdir}/${target}`);
+            }).forEach(function (filename) {
+                console.log(`Removing stale album link: ${linkdir}/${filename}`);
+                destfs.remove(`${linkdir}/${filename}`);
+            });
+        });
+    };
+    const writeSlideshowXml = function (frames) {
+        // Each wallpaper stays up for its own duration, then fades into the next, looping back to the first
+        let abspathXml = `/usr/share/backgrounds/${albumname}-slideshow.xml`;
+        let mockpathXml = `${destdir}${abspathXml}`;
+        console.log(`Writing slideshow XML: ${mockpathXml}`);
+        let blocks = frames.map(function (frame, i) {
+            let next = frames[(i + 1) % frames.length];
+            let duration = (frame.img.duration || parseFloat(OPTIONS['slideshow'])).toFixed(1);
+            let transition = (frame.img.transition || parseFloat(OPTIONS['slideshow-transition'])).toFixed(1);
+            return `<static>
+                <duration>${duration}</duration>
+                <file>${escapeXml(frame.abspath)}</file>
+            </static>${ frames.length > 1 ? `
+            <transition>
+                <duration>${transition}</duration>
+                <from>${escapeXml(frame.abspath)}</from>
+                <to>${escapeXml(next.abspath)}</to>
+            </transition>` : '' }`;
+        });
+        destfs.write(mockpathXml, `<background>
+            <starttime>
+                <year>2000</year>
+                <month>01</month>
+                <day>01</day>
+                <hour>00</hour>
+                <minute>00</minute>
+                <second>00</second>
+            </starttime>
+            ${ blocks.join('\n            ') }
+        </background>`);
+        albumItemsArr.push(`<wallpaper delete="false">
+            <name>${escapeXml(packMeta.name)} Slideshow</name>
+            <filename>${escapeXml(abspathXml)}</filename>
+            <options>${OPTIONS['gnome-fill'] || 'zoom'}</options>
+        </wallpaper>`);
+    };
+    // Sums stay out of the tree they describe, so that they never end up in the package; their paths
+    // are relative to it
+    const writeChecksums = function (sumsname, files) {
+        let mockpathSums = `${OPTIONS['checksums-dir'] || path.dirname(finaldir)}/${path.basename(finaldir)}.${sumsname}`;
+        console.log(`Writing checksums: ${mockpathSums}`);
+        destfs.mkdir(path.dirname(mockpathSums));
+        destfs.write(mockpathSums, files.map(function (x) {
+            return x.path;
+        }).sort().map(function (relpath) {
+            let digest = crypto.createHash('sha256').update(destfs.read(`${destdir}/${relpath}`)).digest('hex');
+            return `${digest}  ${relpath}\n`;
+        }).join(''));
+    };
+    const processEntry = function (img) {
+        // console.log(img);
+        let stdname = getStdName(img);
+        let srcimgpath = getSourcePaths(img).main.canonical;
+        // console.log(stdname);
+        // console.log(srcimgpath);
+        let abspathImg = `/usr/share/backgrounds/${stdname}/${stdname}.${img.f}`;
+        let mockpathImg = `${destdir}/${abspathImg}`;
+        let mockpathMds = `${destdir}/usr/share/wallpapers/${stdname}/metadata.desktop`;
+        let mockpathKdeImages = `${destdir}/usr/share/wallpapers/${stdname}/${KDE_LAYOUTS[OPTIONS['kde-layout']].images}`.replace(/\/\.$/, '');
+        let mockpathKdeImagesDark = `${destdir}/usr/share/wallpapers/${stdname}/${KDE_LAYOUTS[OPTIONS['kde-layout']].images_dark}`;
+        let srcimgpathDark = getSourcePaths(img).dark ? getSourcePaths(img).dark.canonical : null;
+        let abspathImgDark = null;
+        if (srcimgpathDark && VARIANT.toUpperCase() !== 'RETRO') {
+            abspathImgDark = `/usr/share/backgrounds/${stdname}/${stdname}-dark.${img.variants.dark.split('.').reverse()[0]}`;
+        };
+
+        // Portraits get the resolution lists turned on their side, and no landscape-only ratios
+        let portrait = img.orientation === 'portrait';
+        const fitOrientation = function (scrsize) {
+            return portrait ? scrsize.split('x').reverse().join('x') : scrsize;
+        };
+        if (VARIANT.toUpperCase() === 'RETRO') {
+            // Retro installs no copy of the source, so everything points at the largest generated image
+            const area = function (scrsize) {
+                let dims = scrsize.split('x');
+                return dims[0] * dims[1];
+            };
+            let largest = RETRO_RESOLUTIONS.reduce(function (best, x) {
+                return area(x) > area(best) ? x : best;
+            });
+            abspathImg = `${mockpathKdeImages.replace(destdir, '')}/${fitOrientation(largest)}.png`;
+        };
+        const wants = function (target) {
+            if ((img.exclude_outputs || []).indexOf(target) === -1) {
+                return true;
+            };
+            console.log(`Skipping ${target} outputs of "${stdname}", excluded in metadata.`);
+            return false;
+        };
+        if (VARIANT.toUpperCase() === 'RETRO' && !wants('retro')) {
+            console.log('');
+            return 'excluded';
+        };
+
+        // Skip or clear entries known from the previous lock
+        let outputCount = outputFiles.length;
+        lockEntries[stdname] = { uname: img.uname, i: img.i, version: img.version || 1, destdir: finaldir, sources: getSourceHashes(img), metadata: getMetadataHash(img), outputs: [] };
+        if (previousLock && previousLock.entries[stdname]) {
+            let prevEntry = previousLock.entries[stdname];
+            // Locks from before metadata was hashed have none, and rebuild everything once
+            if (JSON.stringify(prevEntry.sources) === JSON.stringify(lockEntries[stdname].sources) && prevEntry.metadata === lockEntries[stdname].metadata) {
+                console.log(`Unchanged, skipping: ${stdname}\n`);
+                if ((img.exclude_outputs || []).indexOf('gnome') === -1) {
+                    addAlbumItem(img, abspathImg, abspathImgDark);
+                };
+                lockEntries[stdname].outputs = prevEntry.outputs;
+                prevEntry.outputs.forEach(function (relpath) {
+                    outputFiles.push({ uname: img.uname, src: srcimgpath, path: relpath });
+                });
+                return 'unchanged';
+            };
+            console.log(`Changed, rebuilding: ${stdname}`);
+            // Re-exported images should come with a version bump, so that downstream can tell
+            if (prevEntry.sources.main !== lockEntries[stdname].sources.main && lockEntries[stdname].version <= (prevEntry.version || 1)) {
+                warn(`Image of "${img.t}" of "${img.uname}" changed, but its version is still ${lockEntries[stdname].version}.`);
+            };
+            pruneEntry(stdname);
+        };
+
+        // For RETRO
+        if (VARIANT.toUpperCase() === 'RETRO') {
+            let allResolutions = RETRO_RESOLUTIONS.map(fitOrientation);
+            try {
+                exec(`mkdir -p /tmp/WallColle_${UUID}`);
+            } catch (e) {
+            } finally {
+            };
+
+            // Create directories
+            destfs.mkdir(mockpathKdeImages);
+
+            // Metadata files
+            if (wants('gnome')) {
+                addAlbumItem(img, abspathImg, null);
+            };
+            let wantsKde = wants('kde');
+            if (wantsKde) {
+                console.log(`Writing metadata.desktop: ${mockpathMds}`);
+                writeDesktopEntry(img, mockpathMds);
+            };
+
+            // Symlinks
+            console.log(`Creating symlinks for image "${stdname}"`);
+            if (portrait) {
+                console.log(`Skipping xfce ratios of portrait "${stdname}".`);
+            } else if (wants('xfce')) {
+                [ '1-1', '16-10', '16-9', '21-9', '3-2', '4-3', '5-4' ].map(function (x) {
+                    destfs.symlink(abspathImg, `${destdir}/usr/share/backgrounds/xfce/${stdname}-${x}.png`);
+                });
+            };
+
+            const genRetroImage = function (srcpath, scrsize, imgSpecificPath) {
+                let geometry = ['-resize', scrsize, '-gravity', 'center'];
+                if (img.focus) {
+                    // Fill the screen and crop around the focus point instead of letterboxing
+                    let win = getFocusCrop(probeImageSize(srcpath), scrsize, img.focus);
+                    geometry = ['-resize', `${scrsize}^`, '-crop', `${win.width}x${win.height}+${win.x}+${win.y}`, '+repage'];
+                };
+                let depth = retroDepths[fitOrientation(scrsize)] || OPTIONS['retro-depth'];
+                let colors = Math.pow(2, parseInt(depth));
+                if (depth === '24') {
+                    runConvert([srcpath].concat(getPrepareArgs(srcpath), geometry, ['-quality', '80', `PNG24:${imgSpecificPath}`]));
+                } else if (RETRO_DITHERS[OPTIONS['retro-dither']] === null) {
+                    // pngquant only dithers with Floyd-Steinberg; ordered dithering posterizes to the levels per channel that fit
+                    let reduce = OPTIONS['retro-dither'] === 'ordered'
+                        ? ['-ordered-dither', `o8x8,${Math.max(2, Math.floor(Math.cbrt(colors)))}`, '+dither', '-colors', String(colors)]
+                        : ['-dither', 'Riemersma', '-colors', String(colors)];
+                    runConvert([srcpath].concat(getPrepareArgs(srcpath), geometry, reduce, ['-quality', '80', `PNG8:${imgSpecificPath}`]));
+                } else {
+                    runConvert([srcpath].concat(getPrepareArgs(srcpath), geometry, ['-quality', '80', imgSpecificPath]));
+                    destfs.run(['mv', imgSpecificPath, `${imgSpecificPath}.p`]);
+                    try {
+                        destfs.run(['pngquant'].concat(OPTIONS['retro-dither'] !== '' ? [RETRO_DITHERS[OPTIONS['retro-dither']]] : [], [String(colors), `${imgSpecificPath}.p`, '-o', imgSpecificPath]));
+                    } catch (e) {
+                        if (!OPTIONS['optional-optimization']) {
+                            throw e;
+                        };
+                        // The converted PNG is valid on its own, only larger
+                        warn(`pngquant failed on ${imgSpecificPath}, keeping it unquantized: ${e.message.split('\n')[0]}`);
+                        destfs.run(['cp', `${imgSpecificPath}.p`, imgSpecificPath]);
+                    };
+                    destfs.run(['rm', `${imgSpecificPath}.p`]);
+                };
+                outputFiles.push({ uname: img.uname, src: srcpath, path: imgSpecificPath.replace(`${destdir}/`, '') });
+            };
+            if (srcimgpathDark) {
+                destfs.mkdir(mockpathKdeImagesDark);
+            };
+            let generated = allResolutions.map(function (scrsize) {
+                let imgSpecificPath = `${mockpathKdeImages}/${scrsize}.png`;
+                console.log(`Generating ${stdname} for ${scrsize}`);
+                genRetroImage(srcimgpath, scrsize, imgSpecificPath);
+                if (srcimgpathDark) {
+                    genRetroImage(srcimgpathDark, scrsize, `${mockpathKdeImagesDark}/${scrsize}.png`);
+                };
+                return { scrsize: scrsize, path: imgSpecificPath };
+            });
+            // The screenshot shows the generated size closest in area to 1280x960
+            if (wantsKde && generated.length > 0) {
+                const distance = function (x) {
+                    let dims = x.scrsize.split('x');
+                    return Math.abs(Math.log(dims[0] * dims[1] / (1280 * 960)));
+                };
+                let screenshot = generated.reduce(function (best, x) {
+                    return distance(x) < distance(best) ? x : best;
+                });
+                destfs.symlink(screenshot.path.replace(destdir, ''), `${destdir}/usr/share/wallpapers/${stdname}/screenshot.png`);
+            };
+            console.log(`OK.\n`);
+        } else { // For NORMAL
+            let allResolutions = [
+                '1024x768', '1152x768', '1280x1024', '1280x800', '1280x854', '1280x960', '1366x768',
+                '1440x900', '1440x960', '1600x1200', '1600x900', '1680x1050', '1920x1080', '1920x1200',
+                '2048x1536', '2048x2048', '2160x1440', '2520x1080', '3360x1440', '2560x2048', '2560x1600',
+                '2880x1800', '3000x2000', '3840x2160', '4096x4096', '4500x3000', '5120x4096', '800x600'
+            ].map(fitOrientation);
+
+            // Create directories
+            let wantsKde = wants('kde');
+            destfs.mkdir(`${destdir}/usr/share/backgrounds/${stdname}`);
+            if (wantsKde) {
+                destfs.mkdir(mockpathKdeImages);
+            };
+
+            // Put files
+            if (getPrepareArgs(srcimgpath).length > 0) {
+                console.log(`Converting image: ${srcimgpath}`);
+                runConvert([srcimgpath].concat(getPrepareArgs(srcimgpath), [mockpathImg]));
+            } else {
+                console.log(`Copying image: ${srcimgpath}`);
+                destfs.copy(srcimgpath, mockpathImg);
+            };
+            outputFiles.push({ uname: img.uname, src: srcimgpath, path: abspathImg.slice(1) });
+            if (OPTIONS['sidecar-attribution']) {
+                // For images taken out of the package on their own
+                let abspathTxt = `/usr/share/backgrounds/${stdname}/${stdname}.txt`;
+                destfs.write(`${destdir}${abspathTxt}`, [
+                    `Title:   ${img.displayName}`,
+                    `Artist:  ${img.name}`,
+                    `License: ${img.l}${licenseUrls[img.l] ? ` <${licenseUrls[img.l]}>` : ''}`
+                ].concat(img.uri ? [`Source:  ${img.uri}`] : []).join('\n') + '\n');
+                outputFiles.push({ uname: img.uname, src: srcimgpath, path: abspathTxt.slice(1) });
+            };
+            // The shape of the base image only matters when there are crops to choose from
+            let baseSize = (img.crops && img.crops.length > 0) ? (img.width ? img : probeImageSize(srcimgpath)) : { width: 1, height: 1 };
+            let cropTargets = [{ ratio: baseSize.width / baseSize.height, f: img.f, abspath: abspathImg }];
+            (img.crops || []).forEach(function (crop) {
+                let aspect = String(crop.aspect).split(/[:\-]/);
+                let srcpathCrop = getSourcePaths(img)[`crop-${crop.i}`].canonical;
+                let abspathCrop = `/usr/share/backgrounds/${stdname}/${stdname}-${aspect.join('-')}.${crop.f}`;
+                if (getPrepareArgs(srcpathCrop).length > 0) {
+                    console.log(`Converting crop: ${srcpathCrop}`);
+                    runConvert([srcpathCrop].concat(getPrepareArgs(srcpathCrop), [`${destdir}${abspathCrop}`]));
+                } else {
+                    console.log(`Copying crop: ${srcpathCrop}`);
+                    destfs.copy(srcpathCrop, `${destdir}${abspathCrop}`);
+                };
+                outputFiles.push({ uname: img.uname, src: srcpathCrop, path: abspathCrop.slice(1) });
+                cropTargets.push({ ratio: aspect[0] / aspect[1], f: crop.f, abspath: abspathCrop });
+            });
+            if (srcimgpathDark) {
+                if (getPrepareArgs(srcimgpathDark).length > 0) {
+                    console.log(`Converting dark variant: ${srcimgpathDark}`);
+                    runConvert([srcimgpathDark].concat(getPrepareArgs(srcimgpathDark), [`${destdir}${abspathImgDark}`]));
+                } else {
+                    console.log(`Copying dark variant: ${srcimgpathDark}`);
+                    destfs.copy(srcimgpathDark, `${destdir}${abspathImgDark}`);
+                };
+                outputFiles.push({ uname: img.uname, src: srcimgpathDark, path: abspathImgDark.slice(1) });
+            };
+
+            // Metadata files
+            if (wants('gnome')) {
+                addAlbumItem(img, abspathImg, abspathImgDark);
+            };
+            if (wantsKde) {
+                console.log(`Writing metadata.desktop: ${mockpathMds}`);
+                writeDesktopEntry(img, mockpathMds);
+            };
+
+            // Symlinks
+            console.log(`Creating symlinks for image "${stdname}"`);
+            if (wantsKde) {
+                destfs.symlink(abspathImg, `${destdir}/usr/share/wallpapers/${stdname}/screenshot.${img.f}`);
+            };
+            if (portrait) {
+                console.log(`Skipping xfce ratios of portrait "${stdname}".`);
+            } else if (wants('xfce')) {
+                [ '1-1', '16-10', '16-9', '21-9', '3-2', '4-3', '5-4' ].map(function (x) {
+                    // Each ratio goes to whichever of the base image and its crops is closest in shape
+                    let ratio = x.split('-')[0] / x.split('-')[1];
+                    let target = cropTargets.reduce(function (best, y) {
+                        return Math.abs(Math.log(y.ratio / ratio)) < Math.abs(Math.log(best.ratio / ratio)) ? y : best;
+                    });
+                    destfs.symlink(target.abspath, `${destdir}/usr/share/backgrounds/xfce/${stdname}-${x}.${target.f}`);
+                });
+            };
+
+            // Plasma picks the listed size closest to the screen and scales it. With a single entry it
+            // always gets the original, which is what all the links point at anyway, but it can no
+            // longer tell from the names alone which screens the image suits
+            let kdeResolutions = allResolutions;
+            if (OPTIONS['dedupe-symlinks']) {
+                let size = getCappedSize(img.width ? img : probeImageSize(srcimgpath));
+                kdeResolutions = [`${size.width}x${size.height}`];
+            };
+            kdeResolutions.filter(function () { return wantsKde }).forEach(function (scrsize) {
+                destfs.symlink(abspathImg, `${mockpathKdeImages}/${scrsize}.${img.f}`);
+            });
+            if (abspathImgDark && wantsKde) {
+                let extDark = abspathImgDark.split('.').reverse()[0];
+                destfs.mkdir(mockpathKdeImagesDark);
+                kdeResolutions.forEach(function (scrsize) {
+                    destfs.symlink(abspathImgDark, `${mockpathKdeImagesDark}/${scrsize}.${extDark}`);
+                });
+            };
+            console.log(`OK.\n`);
+        };
+        lockEntries[stdname].outputs = outputFiles.slice(outputCount).map(function (x) { return x.path });
+        return 'built';
+    };
+    let report = {
+        processed: [],
+        filtered: manifestObj.filtered.map(getStdName),
+        warnings: buildWarnings,
+        elapsed: 0,
+        bytes_written: 0
+    };
+    let startTime = Date.now();
+    manifestObj.entries.every(function (img) {
+        let stdname = getStdName(img);
+        let albumCount = albumItemsArr.length;
+        let slideshowCount = slideshowFrames.length;
+        let outputCount = outputFiles.length;
+        let outcome = { entry_name: stdname, ok: true, status: '', version: img.version || 1, changelog: img.changelog || [], excluded_outputs: img.exclude_outputs || [], bytes: 0, outputs: [] };
+        if (img.width) {
+            outcome.source_size = `${img.width}x${img.height}`;
+        };
+        try {
+            outcome.status = profileSpan('processEntry', function () {
+                return processEntry(img);
+            });
+        } catch (e) {
+            passExit(e);
+            console.error(`Error: Failed to process "${stdname}" (wallpaper ${img.i} of "${img.uname}"): ${e.message}`);
+            albumItemsArr.splice(albumCount);
+            slideshowFrames.splice(slideshowCount);
+            outputFiles.splice(outputCount);
+            delete lockEntries[stdname];
+            outcome.ok = false;
+            outcome.status = 'failed';
+            outcome.error = e.message;
+        };
+        outcome.outputs = outputFiles.slice(outputCount).map(function (x) { return x.path });
+        // Unchanged entries are sized too, so that the report covers the whole tree
+        if (outcome.status === 'built' || outcome.status === 'unchanged') {
+            outcome.bytes = outcome.outputs.reduce(function (sum, relpath) {
+                return sum + destfs.read(`${destdir}/${relpath}`).length;
+            }, 0);
+        };
+        if (outcome.status === 'built') {
+            report.bytes_written += outcome.bytes;
+        };
+        report.processed.push(outcome);
+        return outcome.ok || OPTIONS['keep-going'];
+    });
+    report.elapsed = Date.now() - startTime;
+    if (!OPTIONS['keep-going'] && report.processed.some(function (x) { return !x.ok })) {
+        return report;
+    };
+    if (previousLock) {
+        Object.keys(previousLock.entries).filter(function (stdname) {
+            let prevDestdir = previousLock.entries[stdname].destdir;
+            return !lockEntries.hasOwnProperty(stdname) && (prevDestdir === undefined || prevDestdir === finaldir);
+        }).forEach(function (stdname) {
+            console.log(`Removed since previous lock: ${stdname}${OPTIONS.prune ? ', pruning' : ''}`);
+            if (OPTIONS.prune) {
+                pruneEntry(stdname);
+            };
+        });
+    };
+    albumStep(`write the album "${albumname}"`, function () {
+        if (OPTIONS['slideshow'] !== '' && slideshowFrames.length > 0) {
+            writeSlideshowXml(slideshowFrames);
+        };
+        writeAlbumXml(albumItemsArr);
+    });
+    if (OPTIONS['preserve-timestamps']) {
+        // Symlinks are not in outputFiles, so they keep their own times
+        console.log(`Preserving source timestamps...`);
+        albumStep('preserve source timestamps', function () {
+            outputFiles.forEach(function (x) {
+                let srcstat = fs.statSync(x.src);
+                destfs.utimes(`${destdir}/${x.path}`, srcstat.atime, srcstat.mtime);
+            });
+        });
+    };
+    albumStep('write checksums', function () {
+        if (OPTIONS['checksums']) {
+            writeChecksums('SHA256SUMS', outputFiles);
+        };
+        if (OPTIONS['checksums-per-artist']) {
+            groupByArtist(manifestObj.entries).forEach(function (group) {
+                writeChecksums(`SHA256SUMS.${group.uname}`, outputFiles.filter(function (x) { return x.uname === group.uname }));
+            });
+        };
+    });
+    if (OPTIONS['dry-run']) {
+        say(`Dry run, recorded ${destfs.ops.length} operations:`);
+        destfs.ops.forEach(function (op) {
+            say(`    ${op.join(' ')}`);
+        });
+    };
+    report.elapsed = Date.now() - startTime;
+    return report;
+};
+
+// --------------------------------------
+// Begin controller logic
+
+// Options checked and their files loaded, before anything runs
+let tagRoutes = [];
+let retroDepths = {};
+let licenseMap;
+let blocklist;
+let singleMetadata;
+let packMeta;
+let licenseUrls;
+const configure = function () {
+    if (RATINGS.indexOf(OPTIONS['max-rating']) === -1) {
+        console.error(`Error: Unknown rating "${OPTIONS['max-rating']}", expecting one of: ${RATINGS.join(', ')}.`);
+        process.exit(1);
+    };
+
+    try {
+        new TextDecoder(getManifestEncoding());
+    } catch (e) {
+        console.error(`Error: Unknown manifest encoding "${OPTIONS['manifest-encoding']}".`);
+        process.exit(1);
+    };
+
+    if (SORT_ORDERS.indexOf(OPTIONS['sort-by']) === -1) {
+        console.error(`Error: Unknown order "${OPTIONS['sort-by']}" for --sort-by, expecting one of: ${SORT_ORDERS.join(', ')}.`);
+        process.exit(1);
+    };
+
+    if (OPTIONS['min-source'] !== '' && !OPTIONS['min-source'].match(/^\d+x\d+$/)) {
+        console.error(`Error: Bad --min-source "${OPTIONS['min-source']}", expecting WxH.`);
+        process.exit(1);
+    };
+
+    if (OPTIONS['max-resolution'] !== '' && !OPTIONS['max-resolution'].match(/^[1-9]\d*x[1-9]\d*$/)) {
+        console.error(`Error: Bad --max-resolution "${OPTIONS['max-resolution']}", expecting WxH.`);
+        process.exit(1);
+    };
+
+    if (OPTIONS['retro-dither'] !== '' && !RETRO_DITHERS.hasOwnProperty(OPTIONS['retro-dither'])) {
+        console.error(`Error: Unknown dithering "${OPTIONS['retro-dither']}" for --retro-dither, expecting one of: ${Object.keys(RETRO_DITHERS).join(', ')}.`);
+        process.exit(1);
+    };
+
+    if (['1', '2', '4', '8', '24'].indexOf(OPTIONS['retro-depth']) === -1) {
+        console.error(`Error: Unsupported retro depth "${OPTIONS['retro-depth']}", expecting 1, 2, 4, 8 or 24.`);
+        process.exit(1);
+    };
+
+    if (!OPTIONS['thumbnail-width'].match(/^[1-9]\d*$/)) {
+        console.error(`Error: Bad --thumbnail-width "${OPTIONS['thumbnail-width']}", expecting a number of pixels.`);
+        process.exit(1);
+    };
+
+    tagRoutes = OPTIONS['route-tag'].map(function (route) {
+        let mymatch = route.match(/^([^=]+)=(.+)$/);
+        if (!mymatch || mymatch[1].trim() === '' || mymatch[2].trim().replace(/\/+$/, '') === '') {
+            console.error(`Error: Bad --route-tag "${route}", expecting TAG=DIR with neither left empty.`);
+            process.exit(1);
+        };
+        return { tag: mymatch[1].trim(), destdir: mymatch[2].replace(/\/+$/, '') };
+    });
+    tagRoutes.forEach(function (route, i) {
+        if (OPTIONS['split-output'] !== '') {
+            console.error('Error: --route-tag and --split-output cannot be used together.');
+            process.exit(1);
+        };
+        if (OPTIONS['gresource'] !== '') {
+            console.error('Error: --route-tag and --gresource cannot be used together, as the bundle would only hold the default tree.');
+            process.exit(1);
+        };
+        if (OPTIONS['squashfs'] !== '') {
+            console.error('Error: --route-tag and --squashfs cannot be used together, as the image would only hold the default tree.');
+            process.exit(1);
+        };
+        if (tagRoutes.findIndex(function (x) { return path.resolve(x.destdir) === path.resolve(route.destdir) }) !== i || path.resolve(route.destdir) === path.resolve(DESTDIR)) {
+            console.error(`Error: --route-tag ${route.tag} shares its directory "${route.destdir}" with another tree.`);
+            process.exit(1);
+        };
+    });
+
+    // Outputs are removed and relinked under DESTDIR, which must therefore stay clear of the sources
+    [DESTDIR, OPTIONS['split-output']].concat(tagRoutes.map(function (x) { return x.destdir })).filter(function (x) { return x !== '' }).forEach(function (destdir) {
+        let resolved = path.resolve(destdir);
+        [path.resolve(`${PACK_ROOT}/contributors`), path.resolve(PACKPATH), path.resolve('./dist')].filter(function (source) {
+            return source === resolved || source.indexOf(resolved + path.sep) === 0 || resolved === path.resolve('.');
+        }).slice(0, 1).forEach(function (source) {
+            console.error(`Error: Destination "${destdir}" contains "${path.relative('.', source) || '.'}", choose a separate output directory.`);
+            process.exit(1);
+        });
+    });
+
+    if (OPTIONS['plasma-apply-compat'] && OPTIONS['kde-layout'] !== 'standard') {
+        console.error(`Error: --plasma-apply-compat needs the standard KDE layout, where images are found under contents/images.`);
+        process.exit(1);
+    };
+
+    if (!KDE_LAYOUTS.hasOwnProperty(OPTIONS['kde-layout'])) {
+        console.error(`Error: Unknown KDE layout "${OPTIONS['kde-layout']}", expecting ${Object.keys(KDE_LAYOUTS).join(' or ')}.`);
+        process.exit(1);
+    };
+
+    if (['text', 'csv'].indexOf(OPTIONS['manifest-format']) === -1) {
+        console.error(`Error: Unknown manifest format "${OPTIONS['manifest-format']}", expecting text or csv.`);
+        process.exit(1);
+    };
+
+    if (OPTIONS['single-metadata'] !== '' && ['update-checksums', 'scaffold-metadata', 'migrate-metadata'].indexOf(HELPER_MODE) !== -1) {
+        console.error(`Error: --${HELPER_MODE} works on per-contributor metadata files, not with --single-metadata.`);
+        process.exit(1);
+    };
+    if (!OPTIONS['convert-timeout'].match(/^\d*\.?\d+$/)) {
+        console.error(`Error: Bad --convert-timeout "${OPTIONS['convert-timeout']}", expecting a number of seconds.`);
+        process.exit(1);
+    };
+    if (OPTIONS['size-budget'] !== '' && parseSize(OPTIONS['size-budget']) === null) {
+        console.error(`Error: Bad --size-budget "${OPTIONS['size-budget']}", expecting a size like 500M or 2GiB.`);
+        process.exit(1);
+    };
+    ['slideshow', 'slideshow-transition'].forEach(function (optname) {
+        if (OPTIONS[optname] !== '' && !(parseFloat(OPTIONS[optname]) > 0 && OPTIONS[optname].match(/^\d*\.?\d+$/))) {
+            console.error(`Error: Bad --${optname} "${OPTIONS[optname]}", expecting a positive number of seconds.`);
+            process.exit(1);
+        };
+    });
+    if (!OPTIONS['copy-concurrency'].match(/^\d+$/)) {
+        console.error(`Error: Bad --copy-concurrency "${OPTIONS['copy-concurrency']}", expecting a number.`);
+        process.exit(1);
+    };
+
+    if (['', 'zoom', 'scaled', 'centered', 'spanned', 'stretched', 'wallpaper'].indexOf(OPTIONS['gnome-fill']) === -1) {
+        console.error(`Error: Unknown GNOME fill mode "${OPTIONS['gnome-fill']}", expecting zoom, scaled, centered, spanned, stretched or wallpaper.`);
+        process.exit(1);
+    };
+
+    // Resolutions missing here use --retro-depth
+    retroDepths = {};
+    OPTIONS['retro-format-per-res'].split(',').filter(function (x) { return x.trim() !== '' }).forEach(function (pair) {
+        let mymatch = pair.trim().match(/^(\d+x\d+)=(?:png)?(\d+)$/i);
+        if (!mymatch || ['1', '2', '4', '8', '24'].indexOf(mymatch[2]) === -1) {
+            console.error(`Error: Bad --retro-format-per-res item "${pair}", expecting WxH=depth with a depth of 1, 2, 4, 8 or 24.`);
+            process.exit(1);
+        };
+        if (RETRO_RESOLUTIONS.indexOf(mymatch[1]) === -1) {
+            console.error(`Error: ${mymatch[1]} is not a retro resolution, expecting one of ${RETRO_RESOLUTIONS.join(', ')}.`);
+            process.exit(1);
+        };
+        retroDepths[mymatch[1]] = mymatch[2];
+    });
+
+    if (['error', 'skip'].indexOf(OPTIONS['blocklist-action']) === -1) {
+        console.error(`Error: Unknown blocklist action "${OPTIONS['blocklist-action']}", expecting error or skip.`);
+        process.exit(1);
+    };
+
+    if (OPTIONS['validate-desktop']) {
+        try {
+            exec('command -v desktop-file-validate', { stdio: 'pipe' });
+        } catch (e) {
+            warn('desktop-file-validate is not installed, not validating desktop entries.');
+            OPTIONS['validate-desktop'] = false;
+        };
+    };
+    if (OPTIONS['squashfs'] !== '' && !HELPER_MODE && !OPTIONS['dry-run']) {
+        try {
+            exec('command -v mksquashfs', { stdio: 'pipe' });
+        } catch (e) {
+            console.error('Error: mksquashfs is not installed, but --squashfs needs it.');
+            process.exit(1);
+        };
+    };
+    if (OPTIONS['backend-fallback'] !== '' && !HELPER_MODE && !OPTIONS['dry-run'] && findProgram(splitCommand(OPTIONS['backend-fallback'])[0]) === null) {
+        console.error(`Error: ${splitCommand(OPTIONS['backend-fallback'])[0]} is not installed, but --backend-fallback needs it.`);
+        process.exit(1);
+    };
+    if (OPTIONS['gresource'] !== '' && !HELPER_MODE && !OPTIONS['dry-run']) {
+        try {
+            exec('command -v glib-compile-resources', { stdio: 'pipe' });
+        } catch (e) {
+            console.error('Error: glib-compile-resources is not installed, but --gresource needs it.');
+            process.exit(1);
+        };
+    };
+    if (!OPTIONS['gresource-prefix'].match(/^\/[^\s<>&"]*$/)) {
+        console.error(`Error: Bad --gresource-prefix "${OPTIONS['gresource-prefix']}", expecting an absolute resource path.`);
+        process.exit(1);
+    };
+    licenseMap = loadLicenseMap(OPTIONS['license-map']);
+    blocklist = loadBlocklist(OPTIONS['blocklist']);
+    singleMetadata = loadSingleMetadata(OPTIONS['single-metadata']);
+    packMeta = HELPER_MODE ? null : loadPackMeta();
+    licenseUrls = loadLicenseUrls(OPTIONS['license-urls']);
+};
+if (require.main === module) {
+    configure();
+};
+
+if (HELPER_MODE === 'update-checksums') {
+    updateChecksums();
+    process.exit(0);
+};
+
+if (HELPER_MODE === 'scaffold-metadata') {
+    scaffoldMetadata(OPTIONS['scaffold-metadata']);
+    process.exit(0);
+};
+
+if (HELPER_MODE === 'migrate-metadata') {
+    migrateMetadata(OPTIONS['migrate-metadata']);
+    process.exit(0);
+};
+
+if (HELPER_MODE === 'lint-manifest') {
+    lintManifest(OPTIONS['lint-manifest']);
+    process.exit(0);
+};
+
+if (HELPER_MODE === 'generate-manifest') {
+    generateManifest(OPTIONS['generate-manifest']);
+    process.exit(0);
+};
+
+// Symlinks are stored as they are: image links point at absolute install paths, and album links are relative within the tree
+const packSquashfs = function (treedir, imagepath) {
+    if (OPTIONS['dry-run']) {
+        console.log(`Would pack ${treedir} into ${imagepath}`);
+        return;
+    };
+    console.log(`Packing squashfs: ${imagepath}`);
+    let args = [treedir, imagepath, '-noappend', '-all-root'];
+    if (OPTIONS['reproducible']) {
+        args = args.concat(['-mkfs-time', '0', '-all-time', '0']);
+    };
+    let result = spawn('mksquashfs', args, { stdio: ['ignore', 'ignore', 'inherit'] });
+    if (result.status !== 0) {
+        console.error(`Error: mksquashfs failed with status ${result.status}.`);
+        process.exit(1);
+    };
+    if (OPTIONS['squashfs-remove-tree']) {
+        console.log(`Removing packed tree: ${treedir}`);
+        fs.rmSync(treedir, { recursive: true, force: true });
+    };
+};
+
+// Resources are the regular files of the tree, since a bundle cannot hold links; the generated
+// manifest is left next to the bundle, so that it can be compiled again by hand
+const packGresource = function (treedir, bundlepath) {
+    if (OPTIONS['dry-run']) {
+        console.log(`Would compile ${treedir} into ${bundlepath}`);
+        return;
+    };
+    let files = [];
+    const walk = function (dirpath) {
+        fs.readdirSync(dirpath, { withFileTypes: true }).forEach(function (dirent) {
+            let anypath = `${dirpath}/${dirent.name}`;
+            if (dirent.isDirectory()) {
+                walk(anypath);
+            } else if (dirent.isFile()) {
+                files.push(path.relative(treedir, anypath));
+            };
+        });
+    };
+    walk(treedir);
+    let manifestpath = `${bundlepath}.xml`;
+    console.log(`Writing GResource manifest: ${manifestpath}`);
+    fs.writeFileSync(manifestpath, `<?xml version="1.0" encoding="UTF-8"?>
+<gresources>
+    <gresource prefix="${escapeXml(OPTIONS['gresource-prefix'])}">
+${files.sort().map(function (relpath) {
+        return `        <file${relpath.match(/\.(xml|desktop|txt)$/) ? ' compressed="true"' : ''}>${escapeXml(relpath)}</file>`;
+    }).join('\n')}
+    </gresource>
+</gresources>
+`);
+    console.log(`Compiling GResource bundle: ${bundlepath}`);
+    let result = spawn('glib-compile-resources', [`--sourcedir=${treedir}`, `--target=${bundlepath}`, manifestpath], { stdio: ['ignore', 'ignore', 'inherit'] });
+    if (result.status !== 0) {
+        console.error(`Error: glib-compile-resources failed with status ${result.status}.`);
+        process.exit(1);
+    };
+};
+
+// Links in a tree that do not end at a file of the same tree; absolute targets are install paths,
+// so they are looked up under the tree root rather than on the build host
+const findDanglingLinks = function (treedir) {
+    let dangling = [];
+    const resolves = function (linkpath) {
+        let current = linkpath;
+        for (let hops = 0; hops < 40; hops++) {
+            let stat = null;
+            try {
+                stat = fs.lstatSync(current);
+            } catch (e) {
+                return false;
+            };
+            if (!stat.isSymbolicLink()) {
+                return true;
+            };
+            let target = fs.readlinkSync(current);
+            current = path.isAbsolute(target) ? path.join(treedir, target) : path.resolve(path.dirname(current), target);
+        };
+        return false;
+    };
+    const walk = function (dirpath) {
+        fs.readdirSync(dirpath, { withFileTypes: true }).forEach(function (dirent) {
+            let anypath = `${dirpath}/${dirent.name}`;
+            if (dirent.isDirectory()) {
+                walk(anypath);
+            } else if (dirent.isSymbolicLink() && !resolves(anypath)) {
+                dangling.push({ link: anypath.slice(treedir.length), target: fs.readlinkSync(anypath) });
+            };
+        });
+    };
+    if (fs.existsSync(treedir)) {
+        walk(treedir);
+    };
+    return dangling;
+};
+
+// total covers the whole tree, metadata and albums included; the largest wallpapers are listed as hints.
+// Tells whether the output fits
+const checkSizeBudget = function (report, total) {
+    let budget = parseSize(OPTIONS['size-budget']);
+    if (total <= budget) {
+        console.log(`Output is ${formatSize(total)}, within the budget of ${formatSize(budget)}.`);
+        return true;
     };
-    return str + (new Array(len-str.length)).fill(' ').join('');
+    console.error(`Error: Output is ${formatSize(total)}, over the budget of ${formatSize(budget)} by ${formatSize(total - budget)}.`);
+    console.error('Largest wallpapers:');
+    report.processed.slice().sort(function (a, b) {
+        return b.bytes - a.bytes;
+    }).slice(0, 5).forEach(function (x) {
+        console.error(`    ${padright(formatSize(x.bytes), 10)}   ${x.entry_name}`);
+    });
+    return false;
 };
 
-const getUserManifest = function (username) {
-    let userdata = JSON.parse(fs.readFileSync(`./contributors/${username}/me.json`).toString());
-    userdata.wallpapers.map(function (x, i) {
-        ['uname','name','uri','email'].map(function (keyname) {
-            userdata.wallpapers[i][keyname] = userdata[keyname];
+// Watch mode supervises child builds, so that a failing build does not end the session
+const watchPack = function () {
+    let lockpath = OPTIONS['write-lock'] || `${os.tmpdir()}/wallcolle-watch-${process.pid}.json`;
+    let args = [];
+    for (let i = 2; i < process.argv.length; i++) {
+        if (process.argv[i] === '--watch') {
+            continue;
+        } else if (process.argv[i] === '--only-changed') {
+            i += 1;
+            continue;
+        };
+        args.push(process.argv[i]);
+    };
+    if (OPTIONS['write-lock'] === '') {
+        args.push('--write-lock', lockpath);
+    };
+    let timer = null;
+    let pending = [];
+    // Entries hash their own metadata, but pack-wide metadata may touch every output
+    let packMetaFiles = ['pack-meta', 'single-metadata'].map(function (optname) { return OPTIONS[optname] }).filter(function (x) { return x !== '' });
+    const rebuild = function () {
+        let full = !fs.existsSync(lockpath) || pending.some(function (x) { return packMetaFiles.indexOf(x) !== -1 });
+        let rebuildArgs = full ? args : args.concat(['--only-changed', lockpath]);
+        console.log(`\n[watch] Rebuilding after changes to: ${pending.join(', ') || '(initial build)'}`);
+        pending = [];
+        let result = spawn(process.argv[0], [process.argv[1]].concat(rebuildArgs), { stdio: 'inherit' });
+        console.log(result.status === 0 ? '[watch] Rebuild finished.' : `[watch] Rebuild failed with status ${result.status}.`);
+        console.log('[watch] Waiting for changes...');
+    };
+    // Editors tend to write a file in several steps, so wait for things to settle
+    const scheduleRebuild = function (changedPath) {
+        if (pending.indexOf(changedPath) === -1) {
+            pending.push(changedPath);
+        };
+        clearTimeout(timer);
+        timer = setTimeout(rebuild, 300);
+    };
+    let packdir = PACKPATH.split('/').slice(0, -1).join('/') || '.';
+    let packfiles = [PACKFILE, `${PACKFILE}.pack.json`];
+    fs.watch(packdir, function (event, filename) {
+        if (packfiles.indexOf(filename) !== -1) {
+            scheduleRebuild(`${packdir}/${filename}`);
+        };
+    });
+    fs.watch(`${PACK_ROOT}/contributors`, { recursive: true }, function (event, filename) {
+        if (filename && filename.split('/').pop()[0] !== '.') {
+            scheduleRebuild(`${PACK_ROOT}/contributors/${filename}`);
+        };
+    });
+    ['pack-meta', 'single-metadata'].filter(function (optname) {
+        return OPTIONS[optname] !== '' && fs.existsSync(OPTIONS[optname]);
+    }).forEach(function (optname) {
+        fs.watch(OPTIONS[optname], function () {
+            scheduleRebuild(OPTIONS[optname]);
         });
     });
-    return userdata;
+    rebuild();
 };
 
-const buildDatabase = function () {
-    let db = {};
-    let users = fs.readdirSync('./contributors').filter(function (x) { return x[0] !== '.' });
-    users.forEach(function (username) {
-        db[username] = getUserManifest(username);
+let db;
+let destfs;
+
+// Staging copies sit next to their tree, so that swapping them in is a rename on the same filesystem
+const stageTree = function (destdir) {
+    let stagedir = `${destdir.replace(/\/+$/, '')}.staging-${process.pid}`;
+    fs.rmSync(stagedir, { recursive: true, force: true });
+    process.on('exit', function () {
+        fs.rmSync(stagedir, { recursive: true, force: true });
     });
-    console.log('Building database...');
-    // console.log(db);
-    fs.writeFileSync('./db.json', JSON.stringify(db, '\t', 4));
-    return db;
+    if (fs.existsSync(destdir)) {
+        console.log(`Staging a copy of ${destdir} in ${stagedir}`);
+        let result = spawn('cp', ['-a', destdir, stagedir], { stdio: ['ignore', 'ignore', 'inherit'] });
+        if (result.status !== 0) {
+            console.error(`Error: Cannot stage a copy of "${destdir}".`);
+            process.exit(1);
+        };
+    } else {
+        fs.mkdirSync(stagedir, { recursive: true });
+    };
+    return stagedir;
 };
 
-const renderPackManifest = function (obj) {
-    const renderLines = function (entries) {
-        return entries.map(function (entry) {
-            return [
-                padright(entry.t.slice(0, 32), 33), '   ',
-                padright(entry.name, 17), '   ',
-                entry.l
-            ].join('');
-        }).join('\n');
+const commitTree = function (destdir, stagedir) {
+    let olddir = `${destdir.replace(/\/+$/, '')}.old-${process.pid}`;
+    let hadOld = fs.existsSync(destdir);
+    console.log(`Swapping ${stagedir} into ${destdir}`);
+    if (hadOld) {
+        fs.renameSync(destdir, olddir);
     };
-    return `
-PackName   :   ${PACKNAME}
-DateTime   :   ${obj.date.slice(0, 19).replace('T', ' ')}
-Quantity   :   ${obj.entries.length}
-
-Comments   :
-${obj.comments.join('\n').replace(/# /g, '#     ')}
+    try {
+        fs.renameSync(stagedir, destdir);
+    } catch (e) {
+        if (hadOld) {
+            fs.renameSync(olddir, destdir);
+        };
+        console.error(`Error: Cannot swap "${stagedir}" into "${destdir}", left it as it was: ${e.message}`);
+        process.exit(1);
+    };
+    if (hadOld) {
+        fs.rmSync(olddir, { recursive: true, force: true });
+    };
+};
 
-Title                               Contributor         License
-===============================================================================
-` + renderLines(obj.entries) + '\n';
+// Callbacks of asynchronous work run through here. On the command line an exit from one ends the
+// process as anywhere else; a programmatic call swaps this for one which fails the call instead
+let continueAsync = function (fn) {
+    fn();
 };
 
-const parsePackDef = function (deffile) {
-    let rawdata = deffile.trim().split('\n');
-    let catalog = [];
-    let manifestObj = {
-        title: PACKNAME,
-        date: (new Date()).toISOString(),
-        comments: [],
-        entries: []
-    };
-    rawdata.forEach(function (line, i) {
-        if (line.length > 2 && line.indexOf('# ') !== 0) {
-            // Not comment
-            let mymatch = line.match(/^([0-9A-Za-z_\-]+)\:(\d+)$/);
-            if (mymatch) {
-                // Yes, good declaration line.
-                catalog.push({
-                    uname: mymatch[1],
-                    index: mymatch[2]
-                });
-                manifestObj.entries.push(
-                    db[mymatch[1]].wallpapers[mymatch[2]]
-                );
-            } else {
-                console.error(`Error: Bad declaration in line ${i}. Please fix before proceeding.`);
-                console.error(`Statement: "${line}"`);
-                process.exit(1);
+// Slow destinations do better with a few copies at a time. done runs once every copy has settled, so
+// that nothing exits with copies still underway
+const copyIntoDist = function (entries, done) {
+    let copyLimit = parseInt(OPTIONS['copy-concurrency']) || Infinity;
+    let copyQueue = entries.slice();
+    let copiesRunning = 0;
+    let failures = [];
+    const startCopies = function () {
+        while (copiesRunning < copyLimit && copyQueue.length > 0 && failures.length === 0) {
+            let entryObj = copyQueue.shift();
+            let srcpath = getSourcePaths(entryObj).main.canonical;
+            // Titles are free text, keep separators out of the file name
+            let destpath = `./dist/${entryObj.displayName.replace(/[ \/\\]/g, '_')}.${entryObj.f}`;
+            console.log(`copying: ${srcpath} -> ${destpath}`);
+            if (OPTIONS['dry-run']) {
+                destfs.copy(srcpath, destpath);
+                continue;
             };
-        } else if (line.indexOf('# ') === 0) {
-            // Comment
-            manifestObj.comments.push(line);
+            copiesRunning += 1;
+            fs.copyFile(srcpath, destpath, function (err) {
+                continueAsync(function () {
+                    copiesRunning -= 1;
+                    if (err) {
+                        failures.push(`Error: Cannot copy wallpaper ${entryObj.i} of "${entryObj.uname}" into dist: ${err.message}`);
+                    };
+                    startCopies();
+                });
+            });
         };
-    });
-    return {
-        catalog: catalog,
-        manifestObj: manifestObj,
-        manifestStr: renderPackManifest(manifestObj)
+        if (copiesRunning > 0 || (copyQueue.length > 0 && failures.length === 0)) {
+            return;
+        };
+        if (failures.length > 0) {
+            failures.forEach(function (message) {
+                console.error(message);
+            });
+            process.exit(1);
+        };
+        done();
     };
+    startCopies();
 };
 
-const finisherScript = function (manifestObj) {
-    /* Tree structure:
-    usr
-        share
-            background-properties
-                image-title.myname.xml
-            backgrounds
-                image-title.myname
-                    image-title.myname.png
-                xfce
-                    image-title.myname-1-1.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
-                    image-title.myname-16-10.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
-                    image-title.myname-16-9.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
-                    image-title.myname-21-9.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
-                    image-title.myname-3-2.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
-                    image-title.myname-4-3.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
-                    image-title.myname-5-4.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
-            gnome-background-properties
-                image-title.myname.xml -> /usr/share/background-properties/image-title.myname.xml
-            mate-background-properties
-                image-title.myname.xml -> /usr/share/background-properties/image-title.myname.xml
-            wallpapers
-                image-title.myname
-                    contents
-                        images
-                            1024x768.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
-                            1152x768.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
-                            1280x1024.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
-                            1280x800.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
-                            1280x854.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
-                            1280x960.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
-                            1366x768.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
-                            1440x900.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
-                            1440x960.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
-                            1600x1200.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
-                            1600x900.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
-                            1680x1050.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
-                            1920x1080.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
-                            1920x1200.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
-                            2048x1536.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
-                            2048x2048.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
-                            2160x1440.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
-                            2520x1080.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
-                            3360x1440.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
-                            2560x2048.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
-                            2560x1600.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
-                            2880x1800.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
-                            3000x2000.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
-                            3840x2160.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
-                            4096x4096.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
-                            4500x3000.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
-                            5120x4096.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
-                            800x600.png -> /usr/share/backgrounds/image-title.myname/image-title.myname.png
-                        screenshot.png
-                    metadata.desktop
-    */
-    exec(`
-        ?/usr
-        ?/usr/share
-        ?/usr/share/backgrounds
-        ?/usr/share/backgrounds/xfce
-        ?/usr/share/background-properties
-        ?/usr/share/gnome-background-properties
-        ?/usr/share/mate-background-properties
-        ?/usr/share/wallpapers
-    `.replace(/\s{8}\?/g, `mkdir -p ${DESTDIR}`) );
-    console.log(`------------------------------\n\n`);
-    // console.log(manifestObj);
-    let albumItemsArr = [];
-    const genAlbumItem = function (img, abspathImg) {
-        return `<wallpaper delete="false">
-            <name>${img.t}</name>
-            <filename>${abspathImg}</filename>
-            <artist>${img.name}</artist>
-            <options>zoom</options>
-        </wallpaper>`;
-    };
-    const writeAlbumXml = function (albumItemsArr) {
-        // Write config
-        let abspathXml = `/usr/share/background-properties/${albumname}.xml`;
-        let mockpathXml = `${DESTDIR}/usr/share/background-properties/${albumname}.xml`;
-        console.log(`Writing XML: ${mockpathXml}`);
-        fs.writeFileSync(mockpathXml, `<?xml version='1.0' encoding='UTF-8'?>
-        <!DOCTYPE wallpapers SYSTEM "gnome-wp-list.dtd">
-        <wallpapers>
-            ${ albumItemsArr.join('\n\n') }
-        </wallpapers>`);
-        fs.symlinkSync(abspathXml, `${DESTDIR}/usr/share/gnome-background-properties/${albumname}.xml`);
-        fs.symlinkSync(abspathXml, `${DESTDIR}/usr/share/mate-background-properties/${albumname}.xml`);
-    };
-    let albumname = (PACKNAME[0].toUpperCase() + PACKNAME.slice(1).toLowerCase()).replace(/[^A-Za-z0-9]/g, '.');
-    manifestObj.entries.forEach(function (img) {
-        // console.log(img);
-        let stdname = `${PACKNAME}--${img.uname}--${img.t.replace(/[\s\.\-]/g, '_').replace(/_+/g, '_').replace(/[^A-Za-z0-9\.]/g, '')}`;
-        let srcimgpath = `./contributors/${img.uname}/${img.i}.${img.f}`;
-        // console.log(stdname);
-        // console.log(srcimgpath);
-        let abspathImg = `/usr/share/backgrounds/${stdname}/${stdname}.${img.f}`;
-        let mockpathImg = `${DESTDIR}/${abspathImg}`;
-        let mockpathMds = `${DESTDIR}/usr/share/wallpapers/${stdname}/metadata.desktop`;
+// The selection of the pack, checked against the contributors, without writing anything
+const resolvePack = function () {
+    // "dist" and db.json go through destfs as well, so that a dry run leaves the pack root alone
+    destfs = OPTIONS['dry-run'] ? new MemFs() : StdFs;
+    db = profileSpan('buildDatabase', buildDatabase);
 
-        // For RETRO
-        if (VARIANT.toUpperCase() === 'RETRO') {
-            let allResolutions = [
-                '800x600', '1280x960', '1600x1200', '1920x1200'
-            ];
-            try {
-                exec(`mkdir -p /tmp/WallColle_${UUID}`);
-            } catch (e) {
-            } finally {
-            };
+    console.log(`Trying to create pack for "${PACKNAME}"...\n`);
 
-            // Create directories
-            exec(`mkdir -p ${DESTDIR}/usr/share/backgrounds/${stdname} ${DESTDIR}/usr/share/wallpapers/${stdname} ${DESTDIR}/usr/share/wallpapers/${stdname}/contents ${DESTDIR}/usr/share/wallpapers/${stdname}/contents/images`);
+    let deffile = readPackDef();
+    return profileSpan('parsePackDef', function () {
+        return parsePackDef(deffile);
+    });
+};
 
-            // Put files
-            console.log(`Copying image: ${srcimgpath}`);
-            // fs.copyFileSync(srcimgpath, mockpathImg);
+// done(report, failed) runs once the build is over, with no report when only thumbnails were asked for
+const buildPack = function (packdata, done) {
+    if (OPTIONS['print-resolved']) {
+        say(`# Resolved selection of "${PACKNAME}"`);
+        packdata.manifestObj.entries.forEach(function (entry) {
+            say(`${entry.uname}:${db[entry.uname].wallpapers.indexOf(entry)}`);
+        });
+        say('');
+    };
+    // Thumbnails are all that is written then, leaving dist and db.json as they were
+    if (OPTIONS['thumbnails-only'] !== '') {
+        genThumbnails(packdata.manifestObj, OPTIONS['thumbnails-only']);
+        done(null, false);
+        return;
+    };
+    if (!fs.existsSync('./dist')) {
+        console.log(`Initializing "dist" directory...`);
+        destfs.mkdir('./dist');
+    } else {
+        // Cleaned before anything is written, so that it cannot race the copies below
+        console.log(`Cleaning existing "dist" directory...`);
+        fs.readdirSync('./dist').forEach(function (filename) {
+            destfs.remove(`./dist/${filename}`);
+        });
+    };
+    destfs.write('./db.json', OPTIONS['canonical-json'] ? formatJson(db) : JSON.stringify(db, '\t', 4));
+    if (OPTIONS['write-clean-manifest'] !== '') {
+        writeCleanManifest(OPTIONS['write-clean-manifest'], packdata.catalog, packdata.manifestObj.comments);
+    };
+    destfs.write(`./dist/manifest.txt`, packdata.manifestStr);
 
-            // Metadata files
-            albumItemsArr.push(genAlbumItem(img, abspathImg));
-            console.log(`Writing metadata.desktop: ${mockpathMds}`);
-            fs.writeFileSync(mockpathMds, `
-                [Desktop Entry]
-                Name=${img.t}
-
-                X-KDE-PluginInfo-Name=${img.t}
-                X-KDE-PluginInfo-Author=${img.name}
-                X-KDE-PluginInfo-License=${img.l}
-                ${
-                    img.email ? 'X-KDE-PluginInfo-Email=' + img.email : ''
-                }
-            `.trim().replace(/\n\s+/g, '\n'));
+    copyIntoDist(packdata.manifestObj.entries, function () {
+        Object.keys(db).filter(function (uname) {
+            return db[uname].avatarFile && packdata.manifestObj.entries.some(function (x) { return x.uname === uname });
+        }).forEach(function (uname) {
+            console.log(`copying avatar: ${uname}`);
+            destfs.copy(`${getContributorDir(uname)}/${db[uname].avatar}`, `./dist/${db[uname].avatarFile}`);
+        });
 
-            // Symlinks
-            console.log(`Creating symlinks for image "${stdname}"`);
-            [ '1-1', '16-10', '16-9', '21-9', '3-2', '4-3', '5-4' ].map(function (x) {
-                fs.symlinkSync(abspathImg, `${DESTDIR}/usr/share/backgrounds/xfce/${stdname}-${x}.${img.f}`);
+        console.log(packdata.manifestStr);
+        console.log(`\n\nSuccessfully built the pack "${PACKNAME}" with ${packdata.manifestObj.entries.length} wallpapers.\n`);
+        if (packdata.manifestObj.filtered.length > 0) {
+            console.log(`Filtered out ${packdata.manifestObj.filtered.length} wallpapers:`);
+            packdata.manifestObj.filtered.forEach(function (entry) {
+                console.log(`    ${entry.uname}: ${entry.t}`);
             });
-
-            allResolutions.forEach(function (scrsize) {
-                let imgSpecificPath = `${DESTDIR}/usr/share/wallpapers/${stdname}/contents/images/${scrsize}.png`;
-                console.log(`Generating ${stdname} for ${scrsize}`);
-                exec(`convert ${srcimgpath} -resize ${scrsize} -gravity center -quality 80 ${imgSpecificPath}`);
-                exec(`mv ${imgSpecificPath} ${imgSpecificPath}.p`);
-                exec(`pngquant 256 ${imgSpecificPath}.p -o ${imgSpecificPath}`);
-                exec(`rm ${imgSpecificPath}.p`);
-                if (scrsize === '1280x960') {
-                    fs.symlinkSync(imgSpecificPath.replace(DESTDIR, ''), `${DESTDIR}/usr/share/wallpapers/${stdname}/screenshot.png`);
+            console.log('');
+        };
+        console.log(`Now running finisher script...`);
+        let lockEntries = {};
+        let staged = [];
+        let workdirs = [];
+        const buildTree = function (treeManifest, destdir, albumname) {
+            let workdir = (OPTIONS['atomic'] && !OPTIONS['dry-run']) ? stageTree(destdir) : destdir;
+            if (workdir !== destdir) {
+                staged.push({ destdir: destdir, workdir: workdir });
+            };
+            workdirs.push(workdir);
+            return profileSpan('finisherScript', function () {
+                return finisherScript(treeManifest, workdir, albumname, lockEntries, destdir);
+            });
+        };
+        let report;
+        if (OPTIONS['split-output'] === '' && tagRoutes.length === 0) {
+            report = buildTree(packdata.manifestObj, DESTDIR, getAlbumName(PACKNAME));
+        } else {
+            // One independent tree per artist or per routed tag, each with its own album
+            let trees = OPTIONS['split-output'] === '' ? routeByTag(packdata.manifestObj.entries) : groupByArtist(packdata.manifestObj.entries).map(function (group) {
+                return {
+                    label: group.uname,
+                    entries: group.entries,
+                    destdir: `${OPTIONS['split-output']}/${group.uname}`,
+                    albumname: `${getAlbumName(PACKNAME)}.${getAlbumName(group.uname)}`
                 };
             });
-            console.log(`OK.\n`);
-        } else { // For NORMAL
-            let allResolutions = [
-                '1024x768', '1152x768', '1280x1024', '1280x800', '1280x854', '1280x960', '1366x768',
-                '1440x900', '1440x960', '1600x1200', '1600x900', '1680x1050', '1920x1080', '1920x1200',
-                '2048x1536', '2048x2048', '2160x1440', '2520x1080', '3360x1440', '2560x2048', '2560x1600',
-                '2880x1800', '3000x2000', '3840x2160', '4096x4096', '4500x3000', '5120x4096', '800x600'
-            ];
-
-            // Create directories
-            exec(`mkdir -p ${DESTDIR}/usr/share/backgrounds/${stdname} ${DESTDIR}/usr/share/wallpapers/${stdname} ${DESTDIR}/usr/share/wallpapers/${stdname}/contents ${DESTDIR}/usr/share/wallpapers/${stdname}/contents/images`);
-
-            // Put files
-            console.log(`Copying image: ${srcimgpath}`);
-            fs.copyFileSync(srcimgpath, mockpathImg);
-
-            // Metadata files
-            albumItemsArr.push(genAlbumItem(img, abspathImg));
-            console.log(`Writing metadata.desktop: ${mockpathMds}`);
-            fs.writeFileSync(mockpathMds, `
-                [Desktop Entry]
-                Name=${img.t}
-
-                X-KDE-PluginInfo-Name=${img.t}
-                X-KDE-PluginInfo-Author=${img.name}
-                X-KDE-PluginInfo-License=${img.l}
-                ${
-                    img.email === '' ? '' : 'X-KDE-PluginInfo-Email=' + img.email
-                }
-            `.trim().replace(/\n\s+/g, '\n'));
-
-            // Symlinks
-            console.log(`Creating symlinks for image "${stdname}"`);
-            fs.symlinkSync(abspathImg, `${DESTDIR}/usr/share/wallpapers/${stdname}/screenshot.${img.f}`);
-            [ '1-1', '16-10', '16-9', '21-9', '3-2', '4-3', '5-4' ].map(function (x) {
-                fs.symlinkSync(abspathImg, `${DESTDIR}/usr/share/backgrounds/xfce/${stdname}-${x}.${img.f}`);
+            let reports = trees.map(function (tree) {
+                let treeManifest = Object.assign({}, packdata.manifestObj, { entries: tree.entries });
+                console.log(`Building tree for "${tree.label}"...`);
+                return buildTree(treeManifest, tree.destdir, tree.albumname);
             });
-
-            allResolutions.forEach(function (scrsize) {
-                fs.symlinkSync(abspathImg, `${DESTDIR}/usr/share/wallpapers/${stdname}/contents/images/${scrsize}.${img.f}`);
+            report = {
+                processed: [].concat.apply([], reports.map(function (x) { return x.processed })),
+                filtered: packdata.manifestObj.filtered.map(getStdName),
+                warnings: buildWarnings,
+                elapsed: reports.reduce(function (sum, x) { return sum + x.elapsed }, 0),
+                bytes_written: reports.reduce(function (sum, x) { return sum + x.bytes_written }, 0)
+            };
+        };
+        report.processed = report.processed.concat(packdata.manifestObj.failed);
+        report.default = packdata.manifestObj.default ? getStdName(packdata.manifestObj.default) : null;
+        let failures = report.processed.filter(function (x) { return !x.ok });
+        // Sized before staged trees are swapped in, while they are still where they were built
+        let treeBytes = OPTIONS['size-budget'] === '' ? 0 : workdirs.reduce(function (sum, workdir) { return sum + destfs.treeSize(workdir) }, 0);
+        let linksBroken = false;
+        if (OPTIONS['verify-symlinks'] && !OPTIONS['dry-run']) {
+            let dangling = [].concat.apply([], workdirs.map(findDanglingLinks));
+            dangling.forEach(function (x) {
+                warn(`Link ${x.link} points at ${x.target}, which is not in the tree.`);
             });
-            console.log(`OK.\n`);
+            if (dangling.length > 0 && OPTIONS.strict) {
+                console.error(`Error: ${dangling.length} links do not resolve inside the tree.`);
+                linksBroken = true;
+            } else if (dangling.length === 0) {
+                console.log('All links resolve inside the tree.');
+            };
         };
-
+        // A discarded staging tree leaves nothing for a lock to describe
+        let discarded = (failures.length > 0 || linksBroken) && staged.length > 0;
+        if (OPTIONS['write-lock'] !== '' && !discarded) {
+            console.log(`Writing lock: ${OPTIONS['write-lock']}`);
+            fs.writeFileSync(OPTIONS['write-lock'], formatJson({
+                pack: PACKNAME,
+                variant: VARIANT.toUpperCase(),
+                entries: lockEntries
+            }));
+        };
+        say(`Processed ${report.processed.length} wallpapers (${failures.length} failed), wrote ${report.bytes_written} bytes in ${report.elapsed} ms.`);
+        if (discarded) {
+            console.log('Build failed, leaving the destination as it was.');
+        } else {
+            staged.forEach(function (x) {
+                commitTree(x.destdir, x.workdir);
+            });
+        };
+        if (OPTIONS['gresource'] !== '' && failures.length === 0 && !linksBroken) {
+            packGresource(OPTIONS['split-output'] || DESTDIR, OPTIONS['gresource']);
+        };
+        if (OPTIONS['squashfs'] !== '' && failures.length === 0 && !linksBroken) {
+            packSquashfs(OPTIONS['split-output'] || DESTDIR, OPTIONS['squashfs']);
+        };
+        if (OPTIONS['report'] !== '') {
+            fs.writeFileSync(OPTIONS['report'], formatJson(report));
+        };
+        if (OPTIONS['provenance'] !== '') {
+            writeProvenance(OPTIONS['provenance'], lockEntries);
+        };
+        writeProfile();
+        let overBudget = OPTIONS['size-budget'] !== '' && !checkSizeBudget(report, treeBytes);
+        done(report, failures.length > 0 || linksBroken || overBudget);
     });
-    writeAlbumXml(albumItemsArr);
+};
+
+if (require.main === module) {
+    if (OPTIONS['watch']) {
+        watchPack();
+    } else {
+        buildPack(resolvePack(), function (report, failed) {
+            if (failed) {
+                process.exit(1);
+            };
+        });
+    };
+    return;
 };
 
 // --------------------------------------
-// Begin controller logic
+// Programmatic use
+// require('./wallcolle-make.js') gives resolvePack() and buildPack() instead of running the CLI. Both read
+// the pack root from the current directory, like the CLI does, and run in the calling process, one call
+// at a time.
+// Options are keyed by their command line names, with values of the type of their default in OPTIONS:
+// true or false for switches, an array of strings for repeatable options, and a string for the others,
+// where numbers are taken as well. e.g. { 'sort-by': 'stars', 'require-tag': ['nature'], 'keep-going': true }.
+// Helper modes and --watch are for the command line only.
+// Progress is not printed. A call which the CLI would have ended fails with an Error holding what the CLI
+// would have printed to stderr as message, and its exit status as "status"
 
-if (!fs.existsSync('./dist')) {
-    console.log(`Initializing "dist" directory...`);
-    fs.mkdirSync('./dist');
-} else {
-    fs.readdir('./dist', function (err, stdin, stderr) {
-        if (!err) {
-            console.log(`Cleaning existing "dist" directory...`);
-            stdin.forEach(function (filename) {
-                fs.unlinkSync(`./dist/${filename}`);
-            });
+let callRunning = false;
+
+// Each call starts over from the defaults, as a fresh command line would
+const setOptions = function (options) {
+    if (callRunning) {
+        throw new Error('A build is still running, wait for it before the next call.');
+    };
+    Object.keys(OPTION_DEFAULTS).forEach(function (keyname) {
+        OPTIONS[keyname] = Array.isArray(OPTION_DEFAULTS[keyname]) ? OPTION_DEFAULTS[keyname].slice() : OPTION_DEFAULTS[keyname];
+    });
+    Object.keys(options || {}).forEach(function (keyname) {
+        let value = options[keyname];
+        if (!OPTIONS.hasOwnProperty(keyname)) {
+            throw new Error(`Unknown option "${keyname}".`);
+        };
+        if (keyname === 'watch' || HELPER_MODES.indexOf(keyname) !== -1) {
+            throw new Error(`Option "${keyname}" is for the command line only.`);
+        };
+        if (value === undefined || value === null) {
+            return;
+        };
+        if (typeof OPTIONS[keyname] === 'boolean') {
+            if (typeof value !== 'boolean') {
+                throw new TypeError(`Option "${keyname}" is a switch, expecting true or false.`);
+            };
+            OPTIONS[keyname] = value;
+        } else if (Array.isArray(OPTIONS[keyname])) {
+            if (!Array.isArray(value)) {
+                throw new TypeError(`Option "${keyname}" may be repeated, expecting an array.`);
+            };
+            OPTIONS[keyname] = value.map(String);
+        } else {
+            if (typeof value !== 'string' && typeof value !== 'number') {
+                throw new TypeError(`Option "${keyname}" takes a value, expecting a string or a number.`);
+            };
+            OPTIONS[keyname] = String(value);
         };
     });
 };
 
-let db = buildDatabase();
-
-console.log(`Trying to create pack for "${PACKNAME}"...\n`);
-
-fs.readFile(PACKPATH, function (err, stdin, stderr) {
-    if (err) {
-        console.error(`Error: Cannot make "${PACKNAME}".`);
-        process.exit(1);
+// Runs fn(finish) as the CLI would run with these positionals, where fn calls finish(null, result) once it
+// is done; callback(err, result) hears of either that or of the exit the CLI would have made
+const runInProcess = function (positionals, fn, callback) {
+    let saved = { exit: process.exit, log: console.log, error: console.error, say: say, continueAsync: continueAsync };
+    let stderr = [];
+    let finished = false;
+    callRunning = true;
+    const finish = function (err, result) {
+        if (finished) {
+            return;
+        };
+        finished = true;
+        callRunning = false;
+        process.exit = saved.exit;
+        console.log = saved.log;
+        console.error = saved.error;
+        say = saved.say;
+        continueAsync = saved.continueAsync;
+        if (err && err.exitStatus !== undefined) {
+            let status = err.exitStatus;
+            err = new Error(stderr.join('\n').trim() || `wallcolle-make exited with status ${status}`);
+            err.status = status;
+        };
+        callback(err, result);
+    };
+    process.exit = function (status) {
+        let exit = new Error(`wallcolle-make exited with status ${status || 0}`);
+        exit.exitStatus = status || 0;
+        throw exit;
+    };
+    console.log = function () {};
+    say = console.log;
+    console.error = function () {
+        stderr.push(Array.prototype.join.call(arguments, ' '));
+    };
+    continueAsync = function (step) {
+        try {
+            step();
+        } catch (e) {
+            finish(e);
+        };
+    };
+    try {
+        // Nothing is carried over from an earlier call
+        profileStack = [];
+        profileSamples = {};
+        buildWarnings = [];
+        imagePropsCache = {};
+        contributorDirNames = null;
+        imageMagickCaps = null;
+        setPositionals(positionals);
+        configure();
+        fn(finish);
+    } catch (e) {
+        finish(e);
     };
-    let packdata = parsePackDef(stdin.toString());
-    fs.writeFileSync(`./dist/manifest.txt`, packdata.manifestStr);
+};
 
-    packdata.manifestObj.entries.forEach(function (entryObj) {
-        let srcpath = `./contributors/${entryObj.uname}/${entryObj.i}.${entryObj.f}`;
-        let destpath = `./dist/${entryObj.t.replace(/ /g, '_')}.${entryObj.f}`;
-        console.log(`copying: ${srcpath} -> ${destpath}`);
-        fs.copyFile(srcpath, destpath, function (err) {
-            if (err) {
-                throw err;
-                console.error(`Error: Cannot copy the desinated file "${entryObj.uname}/${entryObj.i}.${entryObj.f}". Does it exist?`);
-            };
+module.exports = {
+    // Resolves the selection of a pack without building it, into its wallpapers in pack order: the
+    // metadata of each from its contributor, with short keys, its license as SPDX and "uname" added
+    resolvePack: function (packpath, variant, options) {
+        setOptions(options);
+        let outcome = null;
+        runInProcess([packpath, '', variant], function (finish) {
+            finish(null, resolvePack().manifestObj.entries);
+        }, function (err, entries) {
+            outcome = { err: err, entries: entries };
         });
-    });
-
-    console.log(packdata.manifestStr);
-    console.log(`\n\nSuccessfully built the pack "${PACKNAME}" with ${packdata.manifestObj.entries.length} wallpapers.\n`);
-    console.log(`Now running finisher script...`);
-    finisherScript(packdata.manifestObj);
-});
+        if (outcome.err) {
+            throw outcome.err;
+        };
+        return outcome.entries;
+    },
+    // Builds a pack into destdir, and resolves to the build report, as written by --report, or to null for
+    // --thumbnails-only. A build with failures, such as a --keep-going one, rejects with its report as
+    // "report" when it got that far
+    buildPack: function (packpath, destdir, variant, options) {
+        setOptions(options);
+        return new Promise(function (resolve, reject) {
+            let report = null;
+            runInProcess([packpath, destdir, variant], function (finish) {
+                buildPack(resolvePack(), function (built, failed) {
+                    report = built;
+                    if (failed) {
+                        process.exit(1);
+                    };
+                    finish(null, built);
+                });
+            }, function (err, built) {
+                if (err) {
+                    err.report = report;
+                    reject(err);
+                } else {
+                    resolve(built);
+                };
+            });
+        });
+    },
+    // Helpers without side effects, for the tests
+    normalizeLicense: normalizeLicense,
+    parseDeclaration: parseDeclaration,
+    parseSize: parseSize,
+    getFocusCrop: getFocusCrop,
+    escapeDesktopValue: escapeDesktopValue,
+    escapeDesktopList: escapeDesktopList,
+    escapeXml: escapeXml,
+    groupByArtist: groupByArtist,
+    getAlbumName: getAlbumName,
+    MemFs: MemFs
+};